@@ -1,11 +1,28 @@
-use regulus::prelude::{Atom, State};
+mod repl;
+
+use regulus::prelude::{Atom, ErrorRenderer, State};
 use std::env;
+use std::io::{self, IsTerminal};
 use std::process::exit;
 
+/// Creates the renderer for uncaught exceptions,
+/// which uses colors if stderr is a terminal and `NO_COLOR` is not set.
+fn error_renderer() -> ErrorRenderer {
+    let colored = env::var_os("NO_COLOR").is_none() && io::stderr().is_terminal();
+    ErrorRenderer::new().colored(colored)
+}
+
+/// Prints the diagnostics that were collected since the last call to stderr.
+fn print_diagnostics(state: &mut State, renderer: &ErrorRenderer) {
+    for diagnostic in state.take_diagnostics() {
+        eprintln!("{}", renderer.render_diagnostic(&diagnostic));
+    }
+}
+
 fn main() {
     let Some(path) = env::args().nth(1) else {
-        eprintln!("error: program file not provided\nusage: cargo run -- PATH",);
-        exit(1);
+        repl::run();
+        return;
     };
 
     let mut state = State::new();
@@ -19,14 +36,17 @@ fn main() {
         }
     }
 
-    match state.run() {
+    let result = state.run();
+    let renderer = error_renderer();
+    print_diagnostics(&mut state, &renderer);
+    match result {
         Ok(atom) => {
             if atom != Atom::Null {
                 println!("{atom}");
             }
         }
         Err(error) => {
-            eprintln!("uncaught exception occured: \n{error}");
+            eprintln!("uncaught exception occured: \n{}", renderer.render(&error));
             exit(1);
         }
     }
@@ -0,0 +1,166 @@
+use crate::{error_renderer, print_diagnostics};
+use regulus::is_reserved_name;
+use regulus::prelude::{Atom, ErrorRenderer, State};
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+available commands:
+  :help         show this message
+  :names        list all defined names, except for internal ones
+  :doc NAME     show the documentation of a function
+  :quit         leave the REPL";
+
+/// Runs an interactive session on stdin until it is closed, `exit` is called or `:quit` is entered.
+pub fn run() {
+    println!("Regulus REPL, enter `:help` for a list of commands");
+
+    // imports are resolved relative to the directory the REPL was started in
+    let mut state = State::new().with_cwd();
+    let mut renderer = error_renderer();
+    let mut buffer = String::new();
+    let mut lines = io::stdin().lock().lines();
+
+    loop {
+        print!("{}", if buffer.is_empty() { ">> " } else { ".. " });
+        io::stdout().flush().unwrap();
+
+        let Some(line) = lines.next() else {
+            println!();
+            return;
+        };
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                eprintln!("reading the input caused an error: {err}");
+                return;
+            }
+        };
+
+        if buffer.is_empty() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            if let Some(command) = trimmed.strip_prefix(':') {
+                if !run_command(&state, command) {
+                    return;
+                }
+                continue;
+            }
+        }
+
+        buffer.push_str(&line);
+        buffer.push('\n');
+        if open_parens(&buffer) > 0 {
+            continue;
+        }
+
+        let result = state.eval_more(&buffer);
+        if let Some(path) = state.last_chunk_path() {
+            renderer.add_source(path, buffer.as_str());
+        }
+        buffer.clear();
+        print_diagnostics(&mut state, &renderer);
+        print_result(result, &renderer);
+        if state.has_exited() {
+            return;
+        }
+    }
+}
+
+fn print_result(result: regulus::prelude::Result<Atom>, renderer: &ErrorRenderer) {
+    match result {
+        Ok(atom) => {
+            if atom != Atom::Null {
+                println!("{atom}");
+            }
+        }
+        Err(error) => eprintln!("{}", renderer.render(&error)),
+    }
+}
+
+/// Executes a meta command (without the leading `:`).
+/// Returns `false` if the session should end.
+fn run_command(state: &State, command: &str) -> bool {
+    let (name, arg) = command
+        .split_once(char::is_whitespace)
+        .map_or((command, ""), |(name, arg)| (name, arg.trim()));
+    match name {
+        "help" => println!("{HELP}"),
+        "quit" | "q" => return false,
+        "names" => println!("{}", defined_names(state).join(" ")),
+        "doc" => match state.storage.get(arg).as_deref() {
+            Some(Atom::Function(f)) => {
+                let argc = f
+                    .argc()
+                    .map_or_else(|| "_".to_string(), |argc| argc.to_string());
+                println!("{arg}({argc}):\n{}", f.doc());
+            }
+            Some(atom) => eprintln!("`{arg}` is not a function, but {atom}"),
+            None => eprintln!("no function `{arg}` found"),
+        },
+        _ => eprintln!("unknown command `:{name}`, enter `:help` for a list of commands"),
+    }
+    true
+}
+
+/// Returns the sorted names of all values, leaving out the internal ones reserved for the STL.
+fn defined_names(state: &State) -> Vec<String> {
+    let mut names = state
+        .storage
+        .all_data()
+        .map(|(name, _)| name)
+        .filter(|name| !is_reserved_name(name))
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+/// Returns how many parentheses are still unclosed in the given code,
+/// ignoring those in strings, chars and comments.
+/// An unclosed string or char literal counts as at least one open parenthesis.
+fn open_parens(code: &str) -> isize {
+    let mut depth = 0;
+    let mut chars = code.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            // literals may span multiple lines, so wait for more input
+            '"' | '\'' if !chars.by_ref().any(|next| next == c) => return depth.max(1),
+            '#' => {
+                chars.by_ref().find(|&next| next == '\n');
+            }
+            _ => (),
+        }
+    }
+    depth
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paren_counting() {
+        assert_eq!(open_parens("print(1)"), 0);
+        assert_eq!(open_parens("def(f, x, _(\n"), 2);
+        assert_eq!(open_parens("print(\")(\""), 1);
+        assert_eq!(open_parens("print(')', # (((\n"), 1);
+        assert_eq!(open_parens("))"), -2);
+        assert_eq!(open_parens("=(s, \"multi\n"), 1);
+    }
+
+    #[test]
+    fn internal_names_are_hidden() {
+        let mut state = State::new().with_cwd();
+        state.eval_more("=(x, 1), =(__stl_y, 2)").unwrap();
+        let names = defined_names(&state);
+        assert!(names.contains(&"x".to_string()));
+        assert!(names.contains(&"print".to_string()));
+        assert!(
+            !names.iter().any(|name| name.starts_with("__")),
+            "{names:?}"
+        );
+    }
+}
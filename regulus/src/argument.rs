@@ -1,4 +1,4 @@
-use crate::exception::{ArgumentError, NameError, TypeError};
+use crate::exception::{ArgumentError, BacktraceFrame, TypeError};
 use crate::list::List;
 use crate::parsing::Span;
 use crate::prelude::*;
@@ -13,10 +13,20 @@ pub enum Argument {
 
 impl Argument {
     pub fn eval<'a>(&'a self, state: &'a mut State) -> Result<Cow<'a, Atom>> {
-        if state.exit_unwind_value.is_some() {
+        if state.exit_unwind_value.is_some() || state.signal.is_some() || state.poll_interrupt() {
             return Ok(Cow::Owned(Atom::Null));
         }
-        state.backtrace.push(self.span().clone());
+        let function = match self {
+            Self::FunctionCall(call, _) => Some(call.name.clone()),
+            Self::Atom(..) | Self::Variable(..) => None,
+        };
+        state
+            .backtrace
+            .push(BacktraceFrame::new(self.span().clone(), function));
+        if let Err(exc) = state.check_depth().and_then(|()| state.consume_fuel()) {
+            state.backtrace.pop();
+            return Err(exc);
+        }
         if let Self::FunctionCall(call, _) = self {
             state.current_doc_comment = Some(call.doc_comment.clone());
             state.current_fn_name = Some(call.name.clone());
@@ -28,14 +38,23 @@ impl Argument {
             Self::FunctionCall(call, _) => call.eval(state).map(Cow::Owned),
             Self::Atom(atom, _) => Ok(Cow::Borrowed(atom)),
             Self::Variable(var, _) => match state.storage.get(var) {
-                Some(value) => Ok(Cow::Borrowed(value)),
-                None => raise!(state, NameError, "No variable named `{var}` found!"),
+                Some(value) => Ok(value),
+                // not using `raise!`, since it would return before the backtrace entry
+                // is removed
+                None => Err(Self::unknown_variable(var, state)),
             },
         };
         state.backtrace.pop();
         res
     }
 
+    // kept out of `eval`, so that its stack frame stays small for deeply nested calls
+    #[cold]
+    #[inline(never)]
+    fn unknown_variable(var: &str, state: &State) -> Exception {
+        state.raise_unknown_name(format!("No variable named `{var}` found!"), var)
+    }
+
     /// Returns the identifier of this variable.
     /// If it is not a variable, it raises an exception with the given error message.
     pub(crate) fn variable(&self, error_msg: &str, state: &State) -> Result<&str> {
@@ -62,6 +81,17 @@ impl Argument {
     }
 }
 
+/// Types that an [`Argument`] can be evaluated and converted into.
+///
+/// This is implemented for every type that implements [`FromAtom`].
+/// It is used for the parameters of functions registered with
+/// [`State::register_fn`](crate::prelude::State::register_fn).
+pub trait FromArgument: Sized {
+    /// Evaluates the argument and converts the result, raising a `TypeError` if it has the
+    /// wrong type.
+    fn from_argument(arg: &Argument, state: &mut State) -> Result<Self>;
+}
+
 macro_rules! argument_eval_as_methods {
     ($($method_name: ident: $variant:ident -> $ty:ty;)*) => {
         #[allow(dead_code, reason = "all methods just provided for completeness")]
@@ -80,9 +110,16 @@ macro_rules! argument_eval_as_methods {
 
 impl Argument {
     pub fn eval_as_string(&self, state: &mut State) -> Result<String> {
-        self.eval(state)?
-            .as_string()
-            .ok_or_else(|| state.raise(TypeError, "{val} is not a list of chars"))
+        let val = self.eval(state)?.into_owned();
+        val.as_string()
+            .ok_or_else(|| state.raise(TypeError, format!("{val} is not a list of chars")))
+    }
+}
+
+impl<T: FromAtom> FromArgument for T {
+    fn from_argument(arg: &Argument, state: &mut State) -> Result<Self> {
+        let atom = arg.eval(state)?.into_owned();
+        Self::from_atom(atom, state)
     }
 }
 
@@ -4,6 +4,7 @@ use crate::prelude::*;
 use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::fmt::{self, Display};
+use std::rc::Rc;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Atom {
@@ -14,6 +15,8 @@ pub enum Atom {
     List(List),
     Function(Function),
     Object(Object),
+    /// An opaque value provided by the host, see [`NativeType`].
+    Native(Native),
 }
 
 impl PartialOrd for Atom {
@@ -47,8 +50,12 @@ impl Atom {
     pub const LIST_TY_ID: i64 = 4;
     pub const FUNCTION_TY_ID: i64 = 5;
     pub const MIN_OBJECT_TY_ID: i64 = 6;
+    /// The type id of module objects returned by `import_module`.
+    pub const MODULE_TY_ID: i64 = i64::MAX - 1;
+    /// The type id of exception objects created by `catch`.
+    pub const EXCEPTION_TY_ID: i64 = i64::MAX - 2;
 
-    pub const fn ty_id(&self) -> i64 {
+    pub fn ty_id(&self) -> i64 {
         match self {
             Self::Int(_) => Self::INT_TY_ID,
             Self::Bool(_) => Self::BOOL_TY_ID,
@@ -57,6 +64,7 @@ impl Atom {
             Self::List(_) => Self::LIST_TY_ID,
             Self::Function(_) => Self::FUNCTION_TY_ID,
             Self::Object(o) => o.ty_id,
+            Self::Native(n) => n.ty().ty_id(),
         }
     }
 
@@ -126,6 +134,7 @@ atom_try_as_variant_methods! {
     list: List -> List;
     function: Function -> Function;
     object: Object -> Object;
+    native: Native -> Native;
 }
 
 impl fmt::Display for Atom {
@@ -154,6 +163,7 @@ impl fmt::Display for Atom {
                     .join(", ")
             ),
             Self::Null => write!(f, "null"),
+            Self::Native(native) => write!(f, "{native}"),
             Self::Object(obj) => {
                 write!(f, "{{")?;
                 let mut ordered = obj.data.iter().collect::<Vec<_>>();
@@ -170,15 +180,29 @@ impl fmt::Display for Atom {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub struct Object {
     pub data: HashMap<String, Atom>,
     pub ty_id: i64,
+    /// The full backtrace of an exception object, kept so that `rethrow` can restore it.
+    /// It is not a field, so the program cannot see it.
+    pub(crate) backtrace: Option<Rc<[BacktraceFrame]>>,
 }
 
 impl Object {
     pub const fn new(data: HashMap<String, Atom>, ty_id: i64) -> Self {
-        Self { data, ty_id }
+        Self {
+            data,
+            ty_id,
+            backtrace: None,
+        }
+    }
+}
+
+// the hidden backtrace does not take part in comparisons
+impl PartialEq for Object {
+    fn eq(&self, other: &Self) -> bool {
+        self.data == other.data && self.ty_id == other.ty_id
     }
 }
 
@@ -1,5 +1,6 @@
+use crate::exception::ArgumentError;
 use crate::prelude::*;
-use crate::state::Directory;
+use crate::state::{Directory, Signal};
 use std::borrow::Cow;
 
 functions! {
@@ -24,6 +25,7 @@ functions! {
     /// This function has an alias: `assign`.
     "="(2) => |state, args| {
         let var = args[0].variable("invalid assignment: tried to assign to a non-variable", state)?;
+        state.check_definition(var, args[0].span());
         let value = args[1].eval(state)?.into_owned();
         state.storage.insert(var, value);
         Ok(Atom::Null)
@@ -41,10 +43,48 @@ functions! {
     /// Repeatedly evaluates the first argument as a boolean.
     /// If it evaluates to true, the second argument is evaluated and the same steps begin again.
     /// If it evaluates to false, the loop ends and `null` is returned.
+    ///
+    /// The loop can be left early with `break()`, and `continue()` skips the rest of the body.
     "while"(2) => |state, args| {
         while args[0].eval_bool(state)? {
-            args[1].eval(state)?;
+            if !state.eval_loop_body(&args[1])? {
+                break;
+            }
+        }
+        Ok(Atom::Null)
+    }
+    /// Stops the current function call and makes it return the given value.
+    /// If no value is given, the function returns `null`.
+    ///
+    /// This also works inside of lazy arguments, for example `if(x, return(1))`
+    /// returns from the function that calls `if`, not from `if` itself.
+    "return"(_) => |state, args| {
+        if state.frame.id == 0 {
+            raise!(state, ArgumentError, "`return` can only be used inside of a function");
+        }
+        let value = match args {
+            [] => Atom::Null,
+            [value] => value.eval(state)?.into_owned(),
+            _ => raise!(state, ArgumentError, "`return` takes at most one argument, found {}", args.len()),
+        };
+        state.signal = Some(Signal::Return(value, state.frame));
+        Ok(Atom::Null)
+    }
+    /// Stops the innermost `while` or `for_in` loop of the current function call.
+    "break"(0) => |state, _| {
+        if state.frame.loop_depth == 0 {
+            raise!(state, ArgumentError, "`break` can only be used inside of a loop");
+        }
+        state.signal = Some(Signal::Break(state.frame));
+        Ok(Atom::Null)
+    }
+    /// Skips the rest of the body of the innermost `while` or `for_in` loop of the current
+    /// function call and continues with the next iteration.
+    "continue"(0) => |state, _| {
+        if state.frame.loop_depth == 0 {
+            raise!(state, ArgumentError, "`continue` can only be used inside of a loop");
         }
+        state.signal = Some(Signal::Continue(state.frame));
         Ok(Atom::Null)
     }
     /// Raises an exception.
@@ -54,10 +94,26 @@ functions! {
     /// The error kind should be a captialized word.
     /// When displaying the error kind, `Error` will be appended implicitly, so the error kind given
     /// here should not end in `Error`, `Exception` or similar.
-    "error"(2) => |state, args| {
-        let kind = args[0].eval_as_string(state)?;
-        let msg = args[1].eval_as_string(state)?;
-        Err(state.raise(kind, msg))
+    ///
+    /// An exception object caught by `catch` can be given as the third argument.
+    /// It is then shown as the cause of the new exception.
+    "error"(_) => |state, args| {
+        let (kind, msg, cause) = match args {
+            [kind, msg] => (kind, msg, None),
+            [kind, msg, cause] => (kind, msg, Some(cause)),
+            _ => raise!(
+                state,
+                ArgumentError,
+                "`error` takes 2 or 3 arguments, found {}", args.len()
+            ),
+        };
+        let kind = kind.eval_as_string(state)?;
+        let msg = msg.eval_as_string(state)?;
+        let exc = state.raise(kind, msg);
+        Err(match cause {
+            Some(cause) => exc.with_cause(Exception::from_argument(cause, state)?),
+            None => exc,
+        })
     }
     /// Evaluates the given value and returns it.
     /// If an exception occurs while evaluating the argument, the exception is converted into a
@@ -87,8 +143,9 @@ functions! {
     /// TODO: think about imports, test them
     "eval"(1) => |state, args| {
         let code = args[0].eval_as_string(state)?;
-        let mut state = State::new().with_code(code);
-        state.file_directory = Directory::FromEval;
+        state.require(state.capabilities.eval, "use `eval`")?;
+        let mut state = state.child().with_code(code);
+        state.file_directory = Directory::Virtual;
         state.run()
     }
     /// Defines a new variable as global and assigns it the given value.
@@ -113,6 +170,74 @@ functions! {
         }
         Ok(Atom::Null)
     }
+    /// Evaluates the first argument and returns its value.
+    /// If it raises an exception, the exception is assigned to the variable given as the second
+    /// argument and the third argument is evaluated and returned instead.
+    ///
+    /// The exception is an object with the fields `kind` (such as `"Index"`), `msg` and
+    /// `backtrace`, which is a list of strings.
+    /// It has no methods, so it cannot be compared with `==`.
+    ///
+    /// If further arguments are given, they are the kinds of exceptions to catch,
+    /// for example `catch(/(a, b), e, 0, "DivideByZero")`.
+    /// All other exceptions are not caught and propagate further.
+    "catch"(_) => |state, args| {
+        let [body, var, handler, kinds @ ..] = args else {
+            raise!(
+                state,
+                ArgumentError,
+                "too few arguments passed to `catch`: expected at least 3, found {}", args.len()
+            );
+        };
+        let var = var.variable("`catch` expects a variable to assign the exception to", state)?;
+        let exc = match body.eval(state).map(Cow::into_owned) {
+            Ok(value) => return Ok(value),
+            // errors caused by unwinding are not relevant and cannot be caught
+            Err(exc) if state.exit_unwind_value.is_some() || state.signal.is_some() => return Err(exc),
+            Err(exc) => exc,
+        };
+        if !kinds.is_empty() {
+            let mut is_caught = false;
+            for kind in kinds {
+                let kind = kind.eval_as_string(state)?;
+                is_caught |= kind == exc.error || kind.strip_suffix("Error") == Some(&exc.error);
+            }
+            if !is_caught {
+                return Err(exc);
+            }
+        }
+        let exc = exc.into_atom(state)?;
+        state.storage.insert(var, exc);
+        handler.eval(state).map(Cow::into_owned)
+    }
+    /// Raises the given exception object again, as caught by `catch`.
+    ///
+    /// The exception keeps its original backtrace.
+    "rethrow"(1) => |state, args| {
+        Err(Exception::from_argument(&args[0], state)?)
+    }
+    /// Evaluates the first argument and returns its value.
+    /// Afterwards, the second argument is evaluated as cleanup code and its value is discarded.
+    ///
+    /// The cleanup also runs if the first argument raises an exception,
+    /// or if the program is stopped by `exit` or left by `return`, `break` or `continue`.
+    /// The exception is kept unless the cleanup raises an exception itself, which replaces it.
+    "try_finally"(2) => |state, args| {
+        let result = args[0].eval(state).map(Cow::into_owned);
+        state.run_cleanup(result, |state| args[1].eval(state).map(drop))
+    }
+    /// Defers the evaluation of the given argument until the current function call ends.
+    ///
+    /// Like the cleanup code of `try_finally`, it runs no matter how the function call ends.
+    /// Deferred code runs in the reverse order it was deferred in and can use the locals of the
+    /// function.
+    "defer"(1) => |state, args| {
+        if state.storage.current_scope == 0 {
+            raise!(state, ArgumentError, "`defer` can only be used inside of a function");
+        }
+        state.storage.defer(args[0].clone());
+        Ok(Atom::Null)
+    }
     // TODO: invent some way for objects to define how they want to be printed.
     // TODO: try then moving this to the STL
     /// Evaluates the given arg and returns a string representation of it.
@@ -128,6 +253,8 @@ functions! {
     /// The first argument is the list, the second the loop variable name for each element and the
     /// third is the body that will be run for each of these elements.
     /// Afterwards, `null` is returned.
+    ///
+    /// The loop can be left early with `break()`, and `continue()` skips the rest of the body.
     // TODO: argument order of seq and loop var is confusing
     "for_in"(3) => |state, args| {
         let v = args[0].eval_list(state)?;
@@ -135,7 +262,9 @@ functions! {
         let loop_body = &args[2];
         for el in v.iter() {
             state.storage.insert(loop_var, el.clone());
-            loop_body.eval(state)?;
+            if !state.eval_loop_body(loop_body)? {
+                break;
+            }
         }
 
         Ok(Atom::Null)
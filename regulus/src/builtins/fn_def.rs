@@ -1,6 +1,9 @@
 use crate::exception::ArgumentError;
 use crate::prelude::*;
+use crate::state::SharedValue;
 use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::rc::{Rc, Weak};
 
 #[derive(Clone)]
 struct FnArgument {
@@ -36,16 +39,87 @@ impl FnArgument {
 // TODO:
 //  think about the distinction between using the def-site state or the call-site state
 //  for evaluating the argument
-fn make_lazy(argument: Argument) -> Atom {
+fn make_lazy(argument: Argument, state: &State) -> Atom {
+    // the argument is evaluated in the module environment of the caller,
+    // and `return`, `break` and `continue` refer to the caller as well
+    let env = state.storage.envs.last().cloned().unwrap_or((0, None));
+    let frame = state.frame;
     Atom::Function(Function::new("", Some(0), move |state, _| {
         state.storage.current_scope -= 1;
+        state.storage.envs.push(env.clone());
+        let callee_frame = std::mem::replace(&mut state.frame, frame);
         let v = argument.eval(state).map(Cow::into_owned);
+        state.frame = callee_frame;
+        state.storage.envs.pop();
         state.storage.current_scope += 1;
         v
     }))
 }
 
-fn define_function(body: &Argument, fn_args: &[Argument], state: &State) -> Result<Atom> {
+/// Collects the names of all variables and functions used in the argument.
+fn referenced_names<'a>(argument: &'a Argument, names: &mut HashSet<&'a str>) {
+    match argument {
+        Argument::FunctionCall(call, _) => {
+            names.insert(&call.name);
+            for arg in &call.args {
+                referenced_names(arg, names);
+            }
+        }
+        Argument::Variable(name, _) => {
+            names.insert(name);
+        }
+        Argument::Atom(..) => {}
+    }
+}
+
+/// Functions defined inside of another function capture the locals they use,
+/// so that they can still use them after the outer function returned.
+///
+/// The captured locals are shared with the outer function, so assignments by either one
+/// are seen by the other.
+/// This includes names that the outer function only assigns after the definition,
+/// like another local function that is defined later.
+/// `name` is the name the function is defined as, if it is defined with `def`.
+/// If the function uses that name, the local it is defined as is created beforehand,
+/// so that it can capture itself and still call itself after the outer function returned.
+fn capture_locals(
+    body: &Argument,
+    fn_args: &[FnArgument],
+    name: Option<&str>,
+    state: &mut State,
+) -> HashMap<String, SharedValue> {
+    if state.storage.current_scope == 0 {
+        return HashMap::new();
+    }
+    let mut names = HashSet::new();
+    referenced_names(body, &mut names);
+    for arg in fn_args {
+        names.remove(arg.name.as_str());
+    }
+    if let Some(name) = name
+        && names.contains(name)
+        && !state.storage.is_in_current_scope(name)
+    {
+        state.storage.insert(name, Atom::Null);
+    }
+    state.storage.capture(names)
+}
+
+/// Runs the code deferred in the current function call with `defer`.
+fn run_deferred(state: &mut State, result: Result<Atom>) -> Result<Atom> {
+    let mut result = result;
+    for code in state.storage.take_deferred() {
+        result = state.run_cleanup(result, |state| code.eval(state).map(drop));
+    }
+    result
+}
+
+fn define_function(
+    body: &Argument,
+    fn_args: &[Argument],
+    name: Option<&str>,
+    state: &mut State,
+) -> Result<Atom> {
     let body = body.clone();
     let function_arg_names = fn_args
         .iter()
@@ -75,6 +149,11 @@ fn define_function(body: &Argument, fn_args: &[Argument], state: &State) -> Resu
         (Some(function_arg_names.len()), function_arg_names.len())
     };
 
+    // only keep a weak reference, since the environment usually contains this function
+    let env = state.current_module_env().as_ref().map(Rc::downgrade);
+
+    let captured = capture_locals(&body, &function_arg_names, name, state);
+
     let function = Function::new(
         state.current_doc_comment.as_ref().unwrap(),
         argc,
@@ -99,7 +178,7 @@ fn define_function(body: &Argument, fn_args: &[Argument], state: &State) -> Resu
                     let mut va_list = Vec::with_capacity(args.len() - idx);
                     for arg in args.iter().skip(idx) {
                         va_list.push(if signature_arg.lazy {
-                            make_lazy(arg.clone())
+                            make_lazy(arg.clone(), state)
                         } else {
                             arg.eval(state)?.into_owned()
                         });
@@ -107,7 +186,7 @@ fn define_function(body: &Argument, fn_args: &[Argument], state: &State) -> Resu
                     arg_values.push((signature_arg.clone(), Atom::new_list(va_list)));
                 } else {
                     let arg_result = if signature_arg.lazy {
-                        make_lazy(args[idx].clone())
+                        make_lazy(args[idx].clone(), state)
                     } else {
                         args[idx].eval(state)?.into_owned()
                     };
@@ -117,7 +196,11 @@ fn define_function(body: &Argument, fn_args: &[Argument], state: &State) -> Resu
 
             // a function call should have its own scope and not leak variables
             // except for globals
+            let call_scope = state.storage.current_scope;
             state.storage.start_scope();
+            for (name, value) in &captured {
+                state.storage.insert_shared(name, Rc::clone(value));
+            }
             for (name, value) in arg_values {
                 if name.lazy {
                     state.storage.current_scope -= 1;
@@ -128,7 +211,15 @@ fn define_function(body: &Argument, fn_args: &[Argument], state: &State) -> Resu
                 }
             }
 
+            state
+                .storage
+                .envs
+                .push((call_scope, env.as_ref().and_then(Weak::upgrade)));
+            let caller_frame = state.enter_function();
             let function_result = body.eval(state).map(Cow::into_owned);
+            let function_result = run_deferred(state, function_result);
+            let function_result = state.leave_function(caller_frame, function_result);
+            state.storage.envs.pop();
             state.storage.end_scope();
 
             function_result
@@ -144,17 +235,20 @@ functions! {
     /// All arguments in between are the names of the function arguments that can be accessed in
     /// the function body.
     /// Values defined in the function are scoped and cannot be accessed outside of the function body.
+    /// Functions defined inside of another function capture the locals they use.
     "def"(_) => |state, args| {
-        let [var, fn_args @ .., body] = args else {
+        let [var_arg, fn_args @ .., body] = args else {
             raise!(
                 state,
                 ArgumentError,
                 "too few arguments passed to `def`: expected at least 2, found {}", args.len()
             );
         };
-        let var = var.variable("invalid function definition: no valid variable was given to define to", state)?;
+        let var = var_arg.variable("invalid function definition: no valid variable was given to define to", state)?;
+        state.check_definition(var, var_arg.span());
 
-        state.storage.insert(var, define_function(body, fn_args, state)?);
+        let function = define_function(body, fn_args, Some(var), state)?;
+        state.storage.insert(var, function);
         Ok(Atom::Null)
     }
     /// Creates a new function and returns it.
@@ -163,10 +257,12 @@ functions! {
     /// All arguments before are the names of the function arguments that can be accessed in
     /// the function body.
     /// Values defined in the function are scoped and cannot be accessed outside of the function body.
+    /// Functions created inside of another function capture the locals they use,
+    /// so that they keep working when they are returned.
     "fn"(_) => |state, args| {
         let Some((body, fn_args)) = args.split_last() else {
             raise!(state, ArgumentError, "`fn` invocation is missing body");
         };
-        define_function(body, fn_args, state)
+        define_function(body, fn_args, None, state)
     }
 }
@@ -1,11 +1,39 @@
+use crate::exception::{ArgumentError, NameError, PermissionError};
 use crate::interned_stdlib::INTERNED_STL;
 use crate::prelude::*;
-use crate::state::Directory;
-use std::fs;
-use std::path::{Path, PathBuf};
+use crate::state::{Directory, LoadedModule};
+use std::collections::HashMap;
+use std::rc::Rc;
 
-fn import(state: &mut State, args: &[Argument]) -> Result<Atom> {
-    let name = args[0].variable(
+/// A module that was loaded by `import` or one of its variants.
+enum Module {
+    Native(Vec<(String, Function)>),
+    /// A module written in Regulus and whether it was just run,
+    /// as opposed to being taken from the module cache.
+    Source(Rc<LoadedModule>, bool),
+}
+
+impl Module {
+    /// Returns the public values defined by the module.
+    ///
+    /// The globals of the module are added to the importing program,
+    /// just like a regular `import` does.
+    fn exports(self, state: &mut State) -> HashMap<String, Atom> {
+        match self {
+            Self::Native(functions) => functions
+                .into_iter()
+                .map(|(name, function)| (name, Atom::Function(function)))
+                .collect(),
+            Self::Source(module, just_run) => {
+                state.storage.add_module_globals(&module, just_run);
+                module.exports.clone()
+            }
+        }
+    }
+}
+
+fn module_name<'a>(state: &State, arg: &'a Argument) -> Result<&'a str> {
+    let name = arg.variable(
         "`import` argument must be a variable, string syntax was removed",
         state,
     )?;
@@ -16,32 +44,70 @@ fn import(state: &mut State, args: &[Argument]) -> Result<Atom> {
             "invalid characters in import name `{name}`, only a-Z, 0-9 and _ are allowed",
         );
     }
+    Ok(name)
+}
+
+/// Finds and runs the module with the given name.
+///
+/// Returns `None` if the module called `exit`, which then also stops the importing program.
+fn load_module(state: &mut State, name: &str) -> Result<Option<Module>> {
+    // STL modules should never be shadowed by other modules
+    if !state.is_stl_module
+        && let Some(functions) = state.native_modules.get(name)
+    {
+        return Ok(Some(Module::Native(functions.clone())));
+    }
 
     // lookup order:
-    // 1. look inside the programs current directory
+    // 1. ask the import resolver, which by default looks inside the programs current directory
     // 2. look in the global stl directory
-    let mut import_state = State::new();
+    let mut import_state = state.child();
     import_state.import_stack.clone_from(&state.import_stack);
     for (global_ident, global_value) in state.storage.all_globals() {
         import_state.storage.add_global(global_ident, global_value);
     }
 
-    if let Directory::Regular(dir_path) = &state.file_directory
-        && let Some(path) = try_resolve_import_in_dir(state, name, dir_path)?
-    {
-        if import_state.import_stack.contains(&path) {
+    let resolved = if state.is_stl_module {
+        None
+    } else {
+        Rc::clone(&state.import_resolver).resolve(name, state)?
+    };
+
+    if let Some(resolved) = resolved {
+        if import_state.import_stack.contains(&resolved.path) {
             raise!(
                 state,
                 "Import",
                 "cyclic import of `{name}` at path `{}` detected",
-                path.display()
+                resolved.path.display()
             );
         }
-        import_state = import_state.with_source_file(&path).unwrap();
-        import_state.import_stack.push(path);
+        if let Some(module) = state.cached_module(&resolved.path) {
+            return Ok(Some(Module::Source(module, false)));
+        }
+        import_state = import_state.with_code(resolved.code);
+        import_state.set_current_file_path(&resolved.path);
+        import_state.file_directory = resolved
+            .directory
+            .map_or(Directory::Virtual, Directory::Regular);
+        import_state.import_stack.push(resolved.path);
     } else if let Some(code) = try_resolve_import_in_stl(name) {
+        let path = format!("<stl:{name}>");
+        if let Some(module) = state.cached_module(path.as_ref()) {
+            return Ok(Some(Module::Source(module, false)));
+        }
         import_state = import_state.with_code(code);
-        import_state.set_current_file_path(format!("<stl:{name}>"));
+        import_state.set_current_file_path(path);
+        import_state.is_stl_module = true;
+    } else if let Directory::Regular(dir_path) = &state.file_directory
+        && !state.capabilities.can_import_from(dir_path)
+    {
+        raise!(
+            state,
+            PermissionError,
+            "`{name}` is not part of the STL and the program is not allowed to import files from `{}`",
+            dir_path.display()
+        );
     } else {
         raise!(
             state,
@@ -50,44 +116,21 @@ fn import(state: &mut State, args: &[Argument]) -> Result<Atom> {
         );
     }
 
+    Ok(run_module(state, import_state)?.map(|module| Module::Source(module, true)))
+}
+
+/// Runs a module on the given state, then stores it in the module cache.
+///
+/// Returns `None` if the module called `exit`, which then also stops the importing program.
+fn run_module(state: &mut State, mut import_state: State) -> Result<Option<Rc<LoadedModule>>> {
     let atom = import_state.run();
 
     if let Some(exit_unwind_value) = import_state.exit_unwind_value {
         state.exit_unwind_value = Some(exit_unwind_value);
-        return Ok(Atom::Null);
+        return Ok(None);
     }
     let atom = atom?;
-    state.storage.extend_from(import_state.storage);
-
-    Ok(atom)
-}
-
-/// Returns:
-/// * `Ok(None)` if the resolution in the given directory failed
-/// * `Ok(Some(path))` if the code was found at `path` in the given directory
-/// * `Err(error)` if reading the directory failed
-fn try_resolve_import_in_dir(
-    state: &State,
-    name: &str,
-    dir_path: &Path,
-) -> Result<Option<PathBuf>> {
-    let paths = fs::read_dir(dir_path)
-        .map_err(|err| {
-            state.raise(
-                "Import",
-                format!(
-                    "error when reading directory `{}`: {err}",
-                    dir_path.display()
-                ),
-            )
-        })?
-        .flatten();
-    for item in paths {
-        if *item.file_name() == *format!("{name}.{FILE_EXTENSION}") {
-            return Ok(Some(item.path()));
-        }
-    }
-    Ok(None)
+    Ok(Some(import_state.finish_module(atom)))
 }
 
 fn try_resolve_import_in_stl(name: &str) -> Option<String> {
@@ -95,26 +138,106 @@ fn try_resolve_import_in_stl(name: &str) -> Option<String> {
 }
 
 functions! {
-    /// Imports a file, either from the stl or the local directory.
+    /// Imports a module, either one added by the host program, a file from the local directory
+    /// or one from the stl.
+    /// All public values of the module, including those it imported itself, are added to the
+    /// program.
+    /// Values whose name starts with `_` are private and can only be used by the module itself.
     /// TODO document the exact algorithm and hierarchy more clearly, also the return value of this function
     "import"(1) => |state, args| {
-        import(state, args)
+        let name = module_name(state, &args[0])?;
+        // running the same call again, for example in a function, is not a mistake
+        if let Some(first) = state.imported_modules.get(name)
+            && first != args[0].span()
+        {
+            state.warn(
+                format!("`{name}` was already imported at {first}"),
+                args[0].span(),
+            );
+        }
+        let module = load_module(state, name)?;
+        state
+            .imported_modules
+            .entry(name.to_string())
+            .or_insert_with(|| args[0].span().clone());
+        Ok(match module {
+            None => Atom::Null,
+            Some(Module::Native(functions)) => {
+                for (fn_name, function) in functions {
+                    state.storage.insert_imported(fn_name, Atom::Function(function));
+                }
+                Atom::Null
+            }
+            Some(Module::Source(module, just_run)) => {
+                state.storage.add_module(&module, just_run);
+                module.result.clone()
+            }
+        })
+    }
+    /// Imports a module like `import`, but returns it as a module object instead of adding its
+    /// values to the program.
+    ///
+    /// The module object only contains the public values the module defined itself,
+    /// not those it imported.
+    /// Values whose name starts with `_` are private.
+    ///
+    /// They can be accessed with `.`, for example `.(m, map)`.
+    /// Functions can also be called directly with `@`, for example `@(m, map, list, f)`.
+    "import_module"(1) => |state, args| {
+        let name = module_name(state, &args[0])?;
+        let Some(module) = load_module(state, name)? else {
+            return Ok(Atom::Null);
+        };
+        let exports = module.exports(state);
+        Ok(Atom::Object(Object::new(exports, Atom::MODULE_TY_ID)))
+    }
+    /// Imports only the given public values from a module.
+    ///
+    /// The first argument is the name of the module, all further arguments are the names of the
+    /// values to import.
+    /// Raises an exception if the module does not define one of the values itself.
+    "import_from"(_) => |state, args| {
+        let Some((module_arg, names)) = args.split_first() else {
+            raise!(state, ArgumentError, "`import_from` takes at least one argument");
+        };
+        let name = module_name(state, module_arg)?;
+        let Some(module) = load_module(state, name)? else {
+            return Ok(Atom::Null);
+        };
+        let mut exports = module.exports(state);
+        for arg in names {
+            let value_name = arg.variable("`import_from` expects the names of values to import", state)?;
+            let Some(value) = exports.remove(value_name) else {
+                raise!(state, NameError, "module `{name}` has no public value `{value_name}`");
+            };
+            state.storage.insert_imported(value_name, value);
+        }
+        Ok(Atom::Null)
     }
     /// Imports the prelude from the STL.
     /// This is implicitly done on startup.
     /// Calling this function manually is not supported.
+    ///
+    /// Does nothing if the prelude was already imported by an earlier run.
     "__builtin_prelude_import"(0) => |state, _| {
-        if matches!(state.file_directory, Directory::InternedSTL) {
+        if matches!(state.file_directory, Directory::InternedSTL) || state.prelude_imported {
             return Ok(Atom::Null);
         }
-        let name = "prelude";
-        let mut import_state = State::new();
-        let code = INTERNED_STL.get(name).expect("`prelude.re` missing from STL");
-        import_state = import_state.with_code(code);
-        import_state.set_current_file_path(format!("<stl:{name}>"));
-        import_state.run()?;
-
-        state.storage.extend_from(import_state.storage);
+        let path = "<stl:prelude>";
+        let (prelude, just_run) = if let Some(prelude) = state.cached_module(path.as_ref()) {
+            (prelude, false)
+        } else {
+            let code = INTERNED_STL.get("prelude").expect("`prelude.re` missing from STL");
+            let mut import_state = state.child().with_code(code);
+            import_state.set_current_file_path(path);
+            import_state.is_stl_module = true;
+            let Some(prelude) = run_module(state, import_state)? else {
+                return Ok(Atom::Null);
+            };
+            (prelude, true)
+        };
+        state.storage.add_module(&prelude, just_run);
+        state.prelude_imported = true;
         Ok(Atom::Null)
     }
 }
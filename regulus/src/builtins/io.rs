@@ -4,6 +4,7 @@ functions! {
     /// Takes no arguments and reads from stdin until a newline is entered.
     /// Returns the read input, excluding the newline, as a string.
     "input"(0) => |state, _| {
+        state.require(state.capabilities.stdin, "read from stdin")?;
         let mut input = String::new();
         match state.stdin.read_line(&mut input) {
             // TODO: consider removing this exception and using `.unwrap_or(&input)` instead
@@ -20,7 +21,15 @@ functions! {
     /// Evaluates the given argument and prints it to stdout, without any additional spaces or newline.
     "write"(1) => |state, args| {
         let s = args[0].eval(state)?.to_string();
+        state.require(state.capabilities.stdout, "write to stdout")?;
         state.write_to_stdout(&s);
         Ok(Atom::Null)
     }
+    /// Evaluates the given argument and prints it to stderr, without any additional spaces or newline.
+    "write_err"(1) => |state, args| {
+        let s = args[0].eval(state)?.to_string();
+        state.require(state.capabilities.stderr, "write to stderr")?;
+        state.write_to_stderr(&s);
+        Ok(Atom::Null)
+    }
 }
@@ -1,5 +1,6 @@
 use crate::prelude::*;
 use std::collections::HashMap;
+use std::rc::Rc;
 
 mod core;
 mod fn_def;
@@ -11,7 +12,17 @@ mod math;
 mod private;
 mod ty;
 
-pub fn all_functions() -> HashMap<String, Atom> {
+thread_local! {
+    /// The builtins are the same for every state, so they are only created once per thread.
+    static BUILTINS: Rc<HashMap<String, Atom>> = Rc::new(all_functions());
+}
+
+/// Returns all builtin functions by name.
+pub fn builtins() -> Rc<HashMap<String, Atom>> {
+    BUILTINS.with(Rc::clone)
+}
+
+fn all_functions() -> HashMap<String, Atom> {
     let mut functions = HashMap::new();
 
     for module in [
@@ -35,6 +35,7 @@ functions! {
     /// Not meant to be used outside of tests.
     "__builtin_print_catch"(1) => |state, args| {
         let exc = args[0].eval(state).expect_err("`__builtin_print_catch` arg should cause exception");
+        state.require(state.capabilities.stderr, "write to stderr")?;
         state.write_to_stderr(&exc.to_string());
         state.write_to_stderr("\n");
         Ok(Atom::Null)
@@ -43,14 +44,28 @@ functions! {
     ///
     /// The stable version of this function is in the `time` STL module.
     "__builtin_now"(0) => |state, _| {
+        state.require(state.capabilities.clock, "read the clock")?;
         Atom::int_from_rust_int(epoch_duration().as_secs(), state)
     }
     /// Returns the nanosecond part of the current time as an integer.
     ///
     /// The stable version of this function is in the `time` STL module.
     "__builtin_now_nanos_part"(0) => |state, _| {
+        state.require(state.capabilities.clock, "read the clock")?;
         Atom::int_from_rust_int(epoch_duration().subsec_nanos(), state)
     }
+    /// Returns the initial seed for the RNG of the `random` STL module.
+    ///
+    /// This is the nanosecond part of the current time,
+    /// or a fixed value if the program may not read the clock.
+    "__builtin_rng_seed"(0) => |state, _| {
+        if state.capabilities.clock {
+            Atom::int_from_rust_int(epoch_duration().subsec_nanos(), state)
+        } else {
+            // any nonzero value works for xorshift
+            Ok(Atom::Int(0x2545_f491))
+        }
+    }
     /// Evaluates both arguments and returns whether they are equal.
     "__builtin_atom_eq"(2) => |state, args| {
         Ok(Atom::Bool(args[0].eval(state)?.into_owned() == *args[1].eval(state)?))
@@ -21,7 +21,7 @@ fn type_(state: &mut State, args: &[Argument]) -> Result<Atom> {
             ),
             Argument::FunctionCall(call, _) => {
                 // TODO: `def` should be allowed; aliases of `=` should be allowed too.
-                if call.name != "=" {
+                if &*call.name != "=" {
                     raise!(state, SyntaxError, "defaulted `type` values must use `=`");
                 }
                 let [Argument::Variable(name, _), value] = call.args.as_slice() else {
@@ -116,6 +116,9 @@ functions! {
     /// Calls a method on an object with the given arguments.
     /// The object itself is implicitly added as the first argument to the method.
     ///
+    /// This also calls methods of native values provided by the host.
+    /// For module objects created by `import_module`, the module is not added as an argument.
+    ///
     /// The first argument is the object, the second the identifier of the method and all further arguments are the arguments to the method.
     ///
     /// This method has an alias: `call_method`.
@@ -123,25 +126,46 @@ functions! {
         let [obj_arg, method, rest @ ..] = args else {
             raise!(state, SyntaxError, "too few arguments for `@`");
         };
-        let obj = obj_arg.eval_object(state)?;
+        let obj = obj_arg.eval(state)?.into_owned();
         let method_name = method.variable("`@` expected the name of a method as second arg", state)?;
-        let Some(func_atom) = obj.data.get(method_name) else {
-            raise!(state, NameError, "object has no method `{method_name}`");
+        let func_atom = match &obj {
+            Atom::Object(obj) => match obj.data.get(method_name) {
+                Some(func_atom) => func_atom.clone(),
+                None => raise!(state, NameError, "object has no method `{method_name}`"),
+            },
+            Atom::Native(native) => match native.ty().method(method_name) {
+                Some(func) => Atom::Function(func.clone()),
+                None => raise!(
+                    state,
+                    NameError,
+                    "native value of type `{}` has no method `{method_name}`",
+                    native.ty().name()
+                ),
+            },
+            val => raise!(state, TypeError, "{val} is not a Object"),
         };
         let Atom::Function(func) = func_atom else {
             raise!(state, TypeError, "{func_atom} is not a function");
         };
+        state.current_doc_comment = Some(String::new());
+        // functions of modules do not take the module as an argument
+        if let Atom::Object(obj) = &obj
+            && obj.ty_id == Atom::MODULE_TY_ID
+        {
+            state.current_fn_name = Some(format!("<module>.{method_name}").into());
+            return func.call(state, rest);
+        }
         let mut args = vec![obj_arg.clone()];
         args.extend_from_slice(rest);
-        state.current_fn_name = Some(format!("<object>.{method_name}"));
-        state.current_doc_comment = Some(String::new());
+        state.current_fn_name = Some(format!("<object>.{method_name}").into());
         func.call(state, &args)
     }
     /// Returns the type id corresponding to the given value.
     ///
     /// A type id is a positive integer. Each primitive type has a distinct ID
     /// (note that its value may change in future versions):
-    /// All objects have arbitrary type ids that are larger than any of the primitive type IDs.
+    /// All objects (and native values provided by the host) have arbitrary type ids that are
+    /// larger than any of the primitive type IDs.
     ///
     /// To access them, use the constants in the `type_id.re` STL module
     /// and the `is_*` (`int`, `bool`, ...) family of functions in that module.
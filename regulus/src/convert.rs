@@ -0,0 +1,354 @@
+//! Conversions between Rust values and [`Atom`]s.
+
+use crate::exception::{OverflowError, TypeError};
+use crate::list::List;
+use crate::prelude::*;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+
+/// Types that can be constructed from an [`Atom`].
+///
+/// The state is only used to attach a backtrace to the exception if the conversion fails.
+pub trait FromAtom: Sized {
+    /// Converts the atom, raising an exception if it has the wrong type or value.
+    fn from_atom(atom: Atom, state: &State) -> Result<Self>;
+}
+
+/// Types that can be converted into an [`Atom`].
+///
+/// The state is only used to attach a backtrace to the exception if the conversion fails.
+pub trait IntoAtom {
+    /// Converts the value, raising an exception if it cannot be represented as an atom.
+    fn into_atom(self, state: &State) -> Result<Atom>;
+}
+
+impl FromAtom for Atom {
+    fn from_atom(atom: Atom, _: &State) -> Result<Self> {
+        Ok(atom)
+    }
+}
+
+impl IntoAtom for Atom {
+    fn into_atom(self, _: &State) -> Result<Atom> {
+        Ok(self)
+    }
+}
+
+macro_rules! convert_variants {
+    ($($variant: ident -> $ty: ty;)*) => {
+        $(
+            impl FromAtom for $ty {
+                fn from_atom(atom: Atom, state: &State) -> Result<Self> {
+                    match atom {
+                        Atom::$variant(v) => Ok(v),
+                        val => raise!(state, TypeError, "{val} is not a {}", stringify!($variant)),
+                    }
+                }
+            }
+
+            impl IntoAtom for $ty {
+                fn into_atom(self, _: &State) -> Result<Atom> {
+                    Ok(Atom::$variant(self))
+                }
+            }
+        )*
+    };
+}
+
+// atom variant name -> rust type;
+convert_variants! {
+    Bool -> bool;
+    Char -> char;
+    List -> List;
+    Function -> Function;
+    Object -> Object;
+    Native -> Native;
+}
+
+macro_rules! convert_ints {
+    ($($ty: ty),*) => {
+        $(
+            impl FromAtom for $ty {
+                fn from_atom(atom: Atom, state: &State) -> Result<Self> {
+                    let Atom::Int(int) = atom else {
+                        raise!(state, TypeError, "{atom} is not a Int");
+                    };
+                    match Self::try_from(int) {
+                        Ok(val) => Ok(val),
+                        Err(e) => raise!(state, OverflowError, "invalid integer: {e}"),
+                    }
+                }
+            }
+
+            impl IntoAtom for $ty {
+                fn into_atom(self, state: &State) -> Result<Atom> {
+                    Atom::int_from_rust_int(self, state)
+                }
+            }
+        )*
+    };
+}
+
+convert_ints!(
+    i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize
+);
+
+impl FromAtom for () {
+    fn from_atom(atom: Atom, state: &State) -> Result<Self> {
+        match atom {
+            Atom::Null => Ok(()),
+            val => raise!(state, TypeError, "{val} is not a Null"),
+        }
+    }
+}
+
+impl IntoAtom for () {
+    fn into_atom(self, _: &State) -> Result<Atom> {
+        Ok(Atom::Null)
+    }
+}
+
+impl FromAtom for String {
+    fn from_atom(atom: Atom, state: &State) -> Result<Self> {
+        match atom.as_string() {
+            Some(s) => Ok(s),
+            None => raise!(state, TypeError, "{atom} is not a list of chars"),
+        }
+    }
+}
+
+impl IntoAtom for String {
+    fn into_atom(self, _: &State) -> Result<Atom> {
+        Ok(Atom::new_string(&self))
+    }
+}
+
+impl IntoAtom for &str {
+    fn into_atom(self, _: &State) -> Result<Atom> {
+        Ok(Atom::new_string(self))
+    }
+}
+
+/// Converts an exception object with the fields `kind`, `msg` and `cause`, as created by `catch`.
+///
+/// If the object was created from an exception, its original backtrace is restored.
+impl FromAtom for Exception {
+    fn from_atom(atom: Atom, state: &State) -> Result<Self> {
+        let mut obj = match atom {
+            Atom::Object(obj) if obj.ty_id == Atom::EXCEPTION_TY_ID => obj,
+            val => raise!(state, TypeError, "{val} is not an Exception"),
+        };
+        let mut field = |name: &str| {
+            obj.data
+                .remove(name)
+                .ok_or_else(|| state.raise(TypeError, format!("exception has no field `{name}`")))
+        };
+        let kind = String::from_atom(field("kind")?, state)?;
+        let msg = String::from_atom(field("msg")?, state)?;
+        let cause = Option::<Self>::from_atom(field("cause")?, state)?;
+        let backtrace = obj.backtrace.as_deref().unwrap_or_default();
+        let exc = Self::with_trace(kind, msg, backtrace);
+        Ok(match cause {
+            Some(cause) => exc.with_cause(cause),
+            None => exc,
+        })
+    }
+}
+
+/// Converts the exception into an object with the fields `kind`, `msg`, `cause`
+/// (another exception or `null`) and `backtrace`,
+/// which is a list of strings in the order they are displayed in.
+impl IntoAtom for Exception {
+    fn into_atom(self, state: &State) -> Result<Atom> {
+        let backtrace = self
+            .shown_backtrace()
+            .map(|span| Atom::new_string(&span.to_string()))
+            .collect();
+        let data = HashMap::from([
+            ("kind".to_string(), Atom::new_string(&self.error)),
+            ("msg".to_string(), Atom::new_string(&self.message())),
+            ("backtrace".to_string(), Atom::new_list(backtrace)),
+            (
+                "cause".to_string(),
+                self.cause.map(|cause| *cause).into_atom(state)?,
+            ),
+        ]);
+        let mut obj = Object::new(data, Atom::EXCEPTION_TY_ID);
+        obj.backtrace = Some(self.backtrace.into());
+        Ok(Atom::Object(obj))
+    }
+}
+
+impl<T: FromAtom> FromAtom for Vec<T> {
+    fn from_atom(atom: Atom, state: &State) -> Result<Self> {
+        let list = List::from_atom(atom, state)?;
+        list.iter()
+            .map(|el| T::from_atom(el.clone(), state))
+            .collect()
+    }
+}
+
+impl<T: IntoAtom> IntoAtom for Vec<T> {
+    fn into_atom(self, state: &State) -> Result<Atom> {
+        Ok(Atom::new_list(
+            self.into_iter()
+                .map(|el| el.into_atom(state))
+                .collect::<Result<_>>()?,
+        ))
+    }
+}
+
+/// `null` is converted to `None`, all other values to `Some`.
+impl<T: FromAtom> FromAtom for Option<T> {
+    fn from_atom(atom: Atom, state: &State) -> Result<Self> {
+        match atom {
+            Atom::Null => Ok(None),
+            val => T::from_atom(val, state).map(Some),
+        }
+    }
+}
+
+/// `None` is converted to `null`.
+impl<T: IntoAtom> IntoAtom for Option<T> {
+    fn into_atom(self, state: &State) -> Result<Atom> {
+        match self {
+            Some(val) => val.into_atom(state),
+            None => Ok(Atom::Null),
+        }
+    }
+}
+
+/// Converts the fields of any object.
+impl<T: FromAtom, S: BuildHasher + Default> FromAtom for HashMap<String, T, S> {
+    fn from_atom(atom: Atom, state: &State) -> Result<Self> {
+        let obj = Object::from_atom(atom, state)?;
+        obj.data
+            .into_iter()
+            .map(|(field, val)| Ok((field, T::from_atom(val, state)?)))
+            .collect()
+    }
+}
+
+/// Constructs an object with [`Atom::new_object`].
+impl<T: IntoAtom, S: BuildHasher> IntoAtom for HashMap<String, T, S> {
+    fn into_atom(self, state: &State) -> Result<Atom> {
+        Ok(Atom::new_object(
+            self.into_iter()
+                .map(|(field, val)| Ok((field, val.into_atom(state)?)))
+                .collect::<Result<_>>()?,
+        ))
+    }
+}
+
+macro_rules! convert_tuples {
+    ($($len: literal: ($($elem: ident),*);)*) => {
+        $(
+            /// Converts a list with exactly the same length as the tuple.
+            impl<$($elem: FromAtom),*> FromAtom for ($($elem,)*) {
+                #[allow(non_snake_case)]
+                fn from_atom(atom: Atom, state: &State) -> Result<Self> {
+                    let list = List::from_atom(atom, state)?;
+                    let [$($elem),*] = list.as_slice() else {
+                        raise!(
+                            state,
+                            TypeError,
+                            "expected a list of length {}, found one of length {}",
+                            $len,
+                            list.len()
+                        );
+                    };
+                    Ok(($($elem::from_atom($elem.clone(), state)?,)*))
+                }
+            }
+
+            /// Constructs a list containing the tuple elements.
+            impl<$($elem: IntoAtom),*> IntoAtom for ($($elem,)*) {
+                #[allow(non_snake_case)]
+                fn into_atom(self, state: &State) -> Result<Atom> {
+                    let ($($elem,)*) = self;
+                    Ok(Atom::new_list(vec![$($elem.into_atom(state)?),*]))
+                }
+            }
+        )*
+    };
+}
+
+convert_tuples! {
+    1: (A);
+    2: (A, B);
+    3: (A, B, C);
+    4: (A, B, C, D);
+    5: (A, B, C, D, E);
+    6: (A, B, C, D, E, F);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::rc::Rc;
+
+    fn roundtrip<T: IntoAtom + FromAtom>(val: T) -> T {
+        let state = State::new();
+        T::from_atom(val.into_atom(&state).unwrap(), &state).unwrap()
+    }
+
+    #[test]
+    fn roundtrips() {
+        assert_eq!(roundtrip(-5i8), -5);
+        assert_eq!(roundtrip(u64::from(u32::MAX)), u64::from(u32::MAX));
+        assert_eq!(roundtrip('x'), 'x');
+        assert_eq!(roundtrip(String::from("abc")), "abc");
+        assert_eq!(roundtrip(vec![Some(1), None]), vec![Some(1), None]);
+        assert_eq!(
+            roundtrip((true, String::from("a"), 3usize)),
+            (true, String::from("a"), 3)
+        );
+        let map = HashMap::from([(String::from("a"), vec![1, 2])]);
+        assert_eq!(roundtrip(map.clone()), map);
+    }
+
+    #[test]
+    fn exception_roundtrip() {
+        let state = State::new();
+        let span = Span::single(Position::new(2, 3), Rc::new(PathBuf::from("a.re")));
+        let exc = Exception::spanned("Index", "out of bounds", &span);
+        let atom = exc.into_atom(&state).unwrap();
+        assert_eq!(atom.ty_id(), Atom::EXCEPTION_TY_ID);
+        let exc = Exception::from_atom(atom, &state).unwrap();
+        assert_eq!(
+            (exc.error.as_str(), exc.msg.as_str()),
+            ("Index", "out of bounds")
+        );
+        assert_eq!(exc.backtrace, vec![span.into()]);
+    }
+
+    #[test]
+    fn conversion_errors() {
+        let state = State::new();
+        let err = |result: Result<Atom>| result.unwrap_err().to_string();
+        assert_eq!(
+            err(u64::MAX.into_atom(&state)),
+            "OverflowError: invalid integer: out of range integral type conversion attempted"
+        );
+        assert_eq!(
+            u8::from_atom(Atom::Int(300), &state)
+                .unwrap_err()
+                .to_string(),
+            "OverflowError: invalid integer: out of range integral type conversion attempted"
+        );
+        assert_eq!(
+            Vec::<i64>::from_atom(Atom::new_string("ab"), &state)
+                .unwrap_err()
+                .to_string(),
+            "TypeError: a is not a Int"
+        );
+        assert_eq!(
+            <(i64, i64)>::from_atom(Atom::new_list(vec![Atom::Int(1)]), &state)
+                .unwrap_err()
+                .to_string(),
+            "TypeError: expected a list of length 2, found one of length 1"
+        );
+        assert_eq!(<Option<bool>>::from_atom(Atom::Null, &state).unwrap(), None);
+    }
+}
@@ -0,0 +1,45 @@
+use crate::parsing::Span;
+use std::fmt;
+
+/// How severe a [`Diagnostic`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Something that is allowed, but likely a mistake.
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Warning => "warning",
+        })
+    }
+}
+
+/// A problem with a program that does not stop it, unlike an [`Exception`](crate::prelude::Exception).
+///
+/// Diagnostics are collected while the program is parsed and while it runs,
+/// and can be read with [`State::diagnostics`](crate::prelude::State::diagnostics).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub msg: String,
+    /// The code the diagnostic is about.
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, msg: impl Into<String>, span: Span) -> Self {
+        Self {
+            severity,
+            msg: msg.into(),
+            span,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}\nat {}", self.severity, self.msg, self.span)
+    }
+}
@@ -1,4 +1,7 @@
 use crate::parsing::Span;
+use crate::state::UnknownName;
+use std::borrow::Cow;
+use std::rc::Rc;
 use std::{error, fmt, result};
 
 #[expect(non_upper_case_globals)]
@@ -8,18 +11,118 @@ mod errors {
     pub(crate) const NameError: &str = "Name";
     pub(crate) const SyntaxError: &str = "Syntax";
     pub(crate) const ArgumentError: &str = "Argument";
+    pub(crate) const ResourceError: &str = "Resource";
+    pub(crate) const RecursionError: &str = "Recursion";
+    pub(crate) const InterruptedError: &str = "Interrupted";
+    pub(crate) const TimeoutError: &str = "Timeout";
+    pub(crate) const PermissionError: &str = "Permission";
 }
 
 pub(crate) use errors::*;
 
+/// An entry of a backtrace: the span of an evaluated argument,
+/// and the name of the function if the argument is a function call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BacktraceFrame {
+    pub span: Span,
+    pub function: Option<Rc<str>>,
+}
+
+impl BacktraceFrame {
+    pub const fn new(span: Span, function: Option<Rc<str>>) -> Self {
+        Self { span, function }
+    }
+}
+
+impl From<Span> for BacktraceFrame {
+    fn from(span: Span) -> Self {
+        Self::new(span, None)
+    }
+}
+
+impl fmt::Display for BacktraceFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.function {
+            Some(function) => write!(f, "{function} ({})", self.span),
+            None => write!(f, "{}", self.span),
+        }
+    }
+}
+
+/// A part of a backtrace as it is shown, see [`Exception::collapsed_backtrace`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShownFrame<'a> {
+    Frame(&'a BacktraceFrame),
+    /// The previous `frames` frames were repeated `times` more times, usually because of
+    /// recursion.
+    Repeated {
+        frames: usize,
+        times: usize,
+    },
+    /// Frames were left out here because the backtrace was too long.
+    Omitted(usize),
+}
+
+/// Frames that were removed from a backtrace because it was too long,
+/// see [`Exception::omitted_frames`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OmittedFrames {
+    /// The index in the backtrace where the frames were removed.
+    pub index: usize,
+    /// How many frames were removed.
+    pub count: usize,
+}
+
+impl fmt::Display for ShownFrame<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Frame(frame) => write!(f, "at {frame}"),
+            Self::Repeated { frames: 1, times } => write!(f, "... repeated {times} more times"),
+            Self::Repeated { frames, times } => {
+                write!(f, "... last {frames} frames repeated {times} more times")
+            }
+            Self::Omitted(frames) => write!(f, "... {frames} frames omitted"),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Exception {
+    /// The message, without the suggestions for unknown names that
+    /// [`message`](Self::message) adds.
     pub msg: String,
     pub error: String,
-    pub backtrace: Vec<Span>,
+    pub backtrace: Vec<BacktraceFrame>,
+    /// The exception that caused this one, if it was raised while handling another exception.
+    pub cause: Option<Box<Self>>,
+    /// The frames that were removed from the backtrace, if it was too long to keep all of them.
+    ///
+    /// This is boxed since it is rarely set and results of evaluations, which contain
+    /// exceptions, should stay small to save stack space.
+    pub omitted_frames: Option<Box<OmittedFrames>>,
+    /// The name that could not be found if this is such a `NameError`.
+    ///
+    /// This is boxed for the same reason as `omitted_frames`.
+    pub(crate) unknown_name: Option<Box<UnknownName>>,
 }
 
 impl Exception {
+    /// Constructs an exception with the given error name and message, but no backtrace.
+    ///
+    /// This is mainly useful in functions created with
+    /// [`Function::from_native`](crate::prelude::Function::from_native), which add the
+    /// backtrace of the call automatically.
+    pub fn new(error: impl Into<String>, msg: impl Into<String>) -> Self {
+        Self {
+            msg: msg.into(),
+            error: error.into(),
+            backtrace: Vec::new(),
+            cause: None,
+            omitted_frames: None,
+            unknown_name: None,
+        }
+    }
+
     /// Constructs an exception with the given error name and message,
     /// using the given span as the only backtrace entry.
     ///
@@ -29,7 +132,10 @@ impl Exception {
         Self {
             msg: msg.into(),
             error: error.into(),
-            backtrace: vec![span.clone()],
+            backtrace: vec![span.clone().into()],
+            cause: None,
+            omitted_frames: None,
+            unknown_name: None,
         }
     }
 
@@ -40,14 +146,40 @@ impl Exception {
     pub fn with_trace(
         error: impl Into<String>,
         msg: impl Into<String>,
-        backtrace: &[Span],
+        backtrace: &[BacktraceFrame],
     ) -> Self {
         Self {
             msg: msg.into(),
             error: error.into(),
             backtrace: backtrace.to_vec(),
+            cause: None,
+            omitted_frames: None,
+            unknown_name: None,
+        }
+    }
+
+    /// Returns the message, followed by suggestions of similar names if this exception was
+    /// raised for a name that could not be found.
+    ///
+    /// The suggestions are only computed when this is called, since many of these exceptions
+    /// are caught without being shown.
+    pub fn message(&self) -> Cow<'_, str> {
+        match self
+            .unknown_name
+            .as_ref()
+            .and_then(|name| name.suggestion())
+        {
+            Some(suggestion) => Cow::Owned(format!("{} ({suggestion})", self.msg)),
+            None => Cow::Borrowed(&self.msg),
         }
     }
+
+    /// Sets the exception that caused this one.
+    #[must_use]
+    pub fn with_cause(mut self, cause: Self) -> Self {
+        self.cause = Some(Box::new(cause));
+        self
+    }
 }
 
 /// Creates an exception wrapped in an `Err` and returns it from the current function or closure.
@@ -68,21 +200,85 @@ macro_rules! raise {
     };
 }
 
+impl Exception {
+    /// Returns the backtrace entries in the order they are shown in, starting with the
+    /// innermost one.
+    ///
+    /// The implicit `_` call around the program is left out.
+    pub fn shown_backtrace(&self) -> impl DoubleEndedIterator<Item = &BacktraceFrame> {
+        // in the case of a syntax error, the backtrace is just the error location,
+        // otherwise, the first entry is the meaningless implicit `_` wrapper
+        let skipped = usize::from(self.backtrace.len() > 1);
+        self.backtrace.iter().skip(skipped).rev()
+    }
+
+    /// Returns the shown backtrace, where sequences of frames that are repeated at least
+    /// [`MIN_COLLAPSED_REPEATS`](Self::MIN_COLLAPSED_REPEATS) more times in a row
+    /// are only shown once.
+    ///
+    /// If frames were omitted, this is marked with [`ShownFrame::Omitted`] where they were
+    /// removed, and repetitions are only collapsed on either side of it.
+    pub fn collapsed_backtrace(&self) -> Vec<ShownFrame<'_>> {
+        let Some(omitted) = self.omitted_frames.as_deref() else {
+            return Self::collapse(&self.shown_backtrace().collect::<Vec<_>>());
+        };
+        // the outer frames still start with the hidden program wrapper
+        let skipped = usize::from(omitted.index > 0);
+        let inner = self.backtrace[omitted.index..]
+            .iter()
+            .rev()
+            .collect::<Vec<_>>();
+        let outer = self.backtrace[skipped..omitted.index]
+            .iter()
+            .rev()
+            .collect::<Vec<_>>();
+        let mut shown = Self::collapse(&inner);
+        shown.push(ShownFrame::Omitted(omitted.count));
+        shown.extend(Self::collapse(&outer));
+        shown
+    }
+
+    fn collapse<'a>(frames: &[&'a BacktraceFrame]) -> Vec<ShownFrame<'a>> {
+        let mut shown = Vec::new();
+        let mut idx = 0;
+        while idx < frames.len() {
+            let rest = &frames[idx..];
+            let repetition = (1..=Self::MAX_COLLAPSED_FRAMES.min(rest.len() / 2))
+                .map(|len| {
+                    let times = rest
+                        .chunks_exact(len)
+                        .skip(1)
+                        .take_while(|chunk| *chunk == &rest[..len])
+                        .count();
+                    (len, times)
+                })
+                .find(|(_, times)| *times >= Self::MIN_COLLAPSED_REPEATS);
+            if let Some((len, times)) = repetition {
+                shown.extend(rest[..len].iter().map(|frame| ShownFrame::Frame(frame)));
+                shown.push(ShownFrame::Repeated { frames: len, times });
+                idx += len * (times + 1);
+            } else {
+                shown.push(ShownFrame::Frame(rest[0]));
+                idx += 1;
+            }
+        }
+        shown
+    }
+
+    /// How often a sequence of frames has to be repeated to be collapsed.
+    pub const MIN_COLLAPSED_REPEATS: usize = 2;
+    /// The longest sequence of frames that is collapsed if it is repeated.
+    const MAX_COLLAPSED_FRAMES: usize = 32;
+}
+
 impl fmt::Display for Exception {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}Error: {}", self.error, self.msg)?;
-        match self.backtrace.len() {
-            0 => (),
-            1 => {
-                // in the case of a syntax error, the backtrace is just the error location
-                write!(f, "\nat {}", self.backtrace[0])?;
-            }
-            _ => {
-                // otherwise, the first entry is the meaningless implicit `_` wrapper
-                for span in self.backtrace.iter().skip(1).rev() {
-                    write!(f, "\nat {span}")?;
-                }
-            }
+        write!(f, "{}Error: {}", self.error, self.message())?;
+        for frame in self.collapsed_backtrace() {
+            write!(f, "\n{frame}")?;
+        }
+        if let Some(cause) = &self.cause {
+            write!(f, "\ncaused by: {cause}")?;
         }
         Ok(())
     }
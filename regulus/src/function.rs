@@ -1,31 +1,31 @@
+use crate::argument::FromArgument;
 use crate::exception::{ArgumentError, NameError};
 use crate::prelude::*;
 use std::fmt;
+use std::path::PathBuf;
 use std::rc::Rc;
 
 #[derive(Debug, Clone)]
 pub struct FunctionCall {
     pub args: Vec<Argument>,
-    pub name: String,
+    pub name: Rc<str>,
     pub doc_comment: String,
 }
 
 impl FunctionCall {
     pub fn eval(&self, state: &mut State) -> Result<Atom> {
-        if state.exit_unwind_value.is_some() {
+        if state.exit_unwind_value.is_some() || state.signal.is_some() {
             return Ok(Atom::Null);
         }
         let name = &self.name;
-        match state.storage.get(name) {
-            Some(atom) => {
-                if let Atom::Function(func) = atom {
-                    func.clone().call(state, &self.args)
-                } else {
-                    raise!(state, NameError, "`{name}` is not a function")
-                }
+        let func = match state.storage.get(name).as_deref() {
+            Some(Atom::Function(func)) => func.clone(),
+            Some(_) => raise!(state, NameError, "`{name}` is not a function"),
+            None => {
+                return Err(state.raise_unknown_name(format!("no function `{name}` found"), name));
             }
-            None => raise!(state, NameError, "no function `{name}` found"),
-        }
+        };
+        func.call(state, &self.args)
     }
 
     /// Returns an approximation of the source code of this function call.
@@ -66,6 +66,27 @@ impl Function {
         }))
     }
 
+    /// Constructs a function from a Rust closure whose parameters are converted automatically.
+    ///
+    /// The argument count is derived from the closure and each argument is evaluated and
+    /// converted with [`FromArgument`], raising a `TypeError` if its value has the wrong type.
+    ///
+    /// If the closure returns an exception without any backtrace, such as one made by
+    /// [`Exception::new`], the backtrace of the call is added to it.
+    pub fn from_native<Args, Ret>(
+        doc: impl Into<String>,
+        f: impl NativeFunction<Args, Ret>,
+    ) -> Self {
+        Self::new(doc, Some(f.argc()), move |state, args| {
+            f.call_native(state, args).map_err(|mut exc| {
+                if exc.backtrace.is_empty() {
+                    exc.backtrace.clone_from(&state.backtrace);
+                }
+                exc
+            })
+        })
+    }
+
     pub fn doc(&self) -> &str {
         self.0.doc.as_str()
     }
@@ -79,27 +100,96 @@ impl Function {
     }
 
     pub fn call(&self, state: &mut State, args: &[Argument]) -> Result<Atom> {
-        if let Some(argc) = self.argc() {
-            let arg_len = args.len();
-            if argc != arg_len {
-                if let Some(current_name) = state.current_fn_name.as_ref() {
-                    raise!(
-                        state,
-                        ArgumentError,
-                        "expected `{argc}` args, found `{arg_len}` args for `{current_name}`",
-                    );
-                }
-                raise!(
-                    state,
-                    ArgumentError,
-                    "expected `{argc}` args, found `{arg_len}` args",
-                )
-            }
+        if let Some(argc) = self.argc()
+            && argc != args.len()
+        {
+            return Err(wrong_arg_count(argc, args.len(), state));
         }
         (self.body())(state, args)
     }
+
+    /// Calls this function with already evaluated arguments.
+    ///
+    /// The values are passed as atom arguments whose spans point to the pseudo-file `<host>`.
+    pub fn call_with_values(&self, state: &mut State, values: Vec<Atom>) -> Result<Atom> {
+        let span = host_span();
+        let args = values
+            .into_iter()
+            .map(|value| Argument::Atom(value, span.clone()))
+            .collect::<Vec<_>>();
+        self.call(state, &args)
+    }
+}
+
+// kept out of `Function::call`, so that its stack frame stays small for deeply nested calls
+#[cold]
+#[inline(never)]
+fn wrong_arg_count(argc: usize, arg_len: usize, state: &State) -> Exception {
+    let msg = match state.current_fn_name.as_ref() {
+        Some(current_name) => {
+            format!("expected `{argc}` args, found `{arg_len}` args for `{current_name}`")
+        }
+        None => format!("expected `{argc}` args, found `{arg_len}` args"),
+    };
+    state.raise(ArgumentError, msg)
+}
+
+/// The span used for values that were passed in from outside of the program.
+pub(crate) fn host_span() -> Span {
+    Span::single(Position::ONE, Rc::new(PathBuf::from("<host>")))
+}
+
+/// A Rust closure that can be turned into a [`Function`] with [`Function::from_native`].
+///
+/// This is implemented for all closures taking up to eight arguments that implement
+/// [`FromArgument`] and returning a `Result` of a type that implements [`IntoAtom`].
+pub trait NativeFunction<Args, Ret>: 'static {
+    /// The number of arguments this closure takes.
+    fn argc(&self) -> usize;
+
+    /// Evaluates and converts the given arguments, then calls the closure with them.
+    ///
+    /// The number of arguments must already have been checked to match [`argc`](Self::argc).
+    fn call_native(&self, state: &mut State, args: &[Argument]) -> Result<Atom>;
+}
+
+macro_rules! impl_native_function {
+    ($($arg: ident),*) => {
+        impl<Func, Ret, $($arg),*> NativeFunction<($($arg,)*), Ret> for Func
+        where
+            Func: Fn($($arg),*) -> Result<Ret> + 'static,
+            Ret: IntoAtom,
+            $($arg: FromArgument,)*
+        {
+            fn argc(&self) -> usize {
+                <[&str]>::len(&[$(stringify!($arg)),*])
+            }
+
+            #[allow(non_snake_case, unused_variables, unused_mut)]
+            fn call_native(&self, state: &mut State, args: &[Argument]) -> Result<Atom> {
+                let mut args = args.iter();
+                $(
+                    let $arg = $arg::from_argument(
+                        args.next().expect("argument count was checked"),
+                        state,
+                    )?;
+                )*
+                self($($arg),*)?.into_atom(state)
+            }
+        }
+    };
 }
 
+impl_native_function!();
+impl_native_function!(A);
+impl_native_function!(A, B);
+impl_native_function!(A, B, C);
+impl_native_function!(A, B, C, D);
+impl_native_function!(A, B, C, D, E);
+impl_native_function!(A, B, C, D, E, F);
+impl_native_function!(A, B, C, D, E, F, G);
+impl_native_function!(A, B, C, D, E, F, G, H);
+
 // the callback cannot be debugged
 impl fmt::Debug for Function {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -111,6 +201,13 @@ impl fmt::Debug for Function {
     }
 }
 
+impl Function {
+    /// Returns whether both refer to the same function, not only to equal ones.
+    pub(crate) fn ptr_eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
 impl PartialEq for Function {
     fn eq(&self, _other: &Self) -> bool {
         false
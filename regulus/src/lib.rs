@@ -15,12 +15,17 @@
 
 mod argument;
 mod atom;
+mod convert;
+mod diagnostic;
 mod exception;
 mod function;
 mod list;
 mod macros;
+mod native;
 mod optimizations;
 mod parsing;
+mod render;
+mod resolver;
 mod state;
 
 mod builtins;
@@ -32,15 +37,21 @@ mod interned_stdlib;
 pub mod prelude {
     pub use crate::{
         FILE_EXTENSION,
-        argument::Argument,
+        argument::{Argument, FromArgument},
         atom::{Atom, Object},
-        exception::{Exception, Result},
-        function::{Function, FunctionBody, FunctionCall},
+        convert::{FromAtom, IntoAtom},
+        diagnostic::{Diagnostic, Severity},
+        exception::{BacktraceFrame, Exception, OmittedFrames, Result, ShownFrame},
+        function::{Function, FunctionBody, FunctionCall, NativeFunction},
         functions,
         list::List,
+        native::{Native, NativeType, NativeValue},
         parsing::{Position, Span},
-        raise, run, run_file,
-        state::{State, Storage, WriteHandle},
+        raise,
+        render::ErrorRenderer,
+        resolver::{FsResolver, ImportResolver, MemoryResolver, ResolvedImport},
+        run, run_file,
+        state::{Capabilities, FsImport, State, Storage, WriteHandle},
     };
 }
 
@@ -55,6 +66,14 @@ pub(crate) fn no_path() -> Rc<PathBuf> {
 /// Files need to end with this extension to be considered by import resolution.
 pub const FILE_EXTENSION: &str = "re";
 
+/// Returns whether the name starts with `__stl` or `__builtin`,
+/// which are reserved for the STL and internal APIs.
+pub fn is_reserved_name(name: &str) -> bool {
+    ["__stl", "__builtin"]
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+}
+
 /// A convenient helper for directly running one file program.
 ///
 /// Returns only the result of running the program, not the final state.
@@ -0,0 +1,187 @@
+//! Opaque values owned by the host program.
+
+use crate::prelude::*;
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt;
+use std::ptr;
+use std::rc::Rc;
+
+/// Rust values that can be stored inside of a [`Native`].
+///
+/// This is implemented for every type that implements [`Display`](fmt::Display),
+/// which is used when the value is printed by the program.
+pub trait NativeValue: Any + fmt::Display {
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<T: Any + fmt::Display> NativeValue for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+struct NativeTypeInner {
+    name: String,
+    ty_id: i64,
+    methods: HashMap<String, Function>,
+}
+
+/// A host-defined type of [`Native`] values, created with [`State::make_native_type`].
+///
+/// Its methods can be called by the program with `@`, just like the methods of objects.
+#[derive(Clone)]
+pub struct NativeType(Rc<NativeTypeInner>);
+
+impl NativeType {
+    pub(crate) fn new(name: String, ty_id: i64, methods: Vec<(&str, Function)>) -> Self {
+        let mut methods = methods
+            .into_iter()
+            .map(|(name, method)| (name.to_string(), method))
+            .collect::<HashMap<_, _>>();
+        // by default, values are only equal to themselves
+        methods.entry("==".to_string()).or_insert_with(|| {
+            Function::new("", Some(2), |state, args| {
+                let lhs = args[0].eval(state)?.into_owned();
+                Ok(Atom::Bool(lhs == *args[1].eval(state)?))
+            })
+        });
+        Self(Rc::new(NativeTypeInner {
+            name,
+            ty_id,
+            methods,
+        }))
+    }
+
+    /// The name of this type.
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    /// The type id of this type, as returned by `type_id`.
+    pub fn ty_id(&self) -> i64 {
+        self.0.ty_id
+    }
+
+    /// Returns the method with the given name.
+    pub fn method(&self, name: &str) -> Option<&Function> {
+        self.0.methods.get(name)
+    }
+
+    /// Wraps the given value into an atom of this type.
+    pub fn wrap(&self, value: impl NativeValue) -> Atom {
+        Atom::Native(Native {
+            value: Rc::new(value),
+            ty: self.clone(),
+        })
+    }
+}
+
+/// An opaque Rust value that can be passed around by the program.
+///
+/// Cloning it is cheap and does not clone the underlying value.
+/// Two natives are only equal if they refer to the same underlying value.
+#[derive(Clone)]
+pub struct Native {
+    value: Rc<dyn NativeValue>,
+    ty: NativeType,
+}
+
+impl Native {
+    /// The type of this value.
+    pub const fn ty(&self) -> &NativeType {
+        &self.ty
+    }
+
+    /// Returns a reference to the underlying value if it has the type `T`.
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        (*self.value).as_any().downcast_ref()
+    }
+}
+
+impl PartialEq for Native {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::addr_eq(Rc::as_ptr(&self.value), Rc::as_ptr(&other.value))
+    }
+}
+
+impl fmt::Display for Native {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+// the value cannot be debugged
+impl fmt::Debug for Native {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Native")
+            .field("ty", &self.ty.name())
+            .field("value", &"..")
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    struct Counter(Cell<i64>);
+
+    impl fmt::Display for Counter {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "<counter at {}>", self.0.get())
+        }
+    }
+
+    fn counter(native: &Native) -> &Counter {
+        native.downcast_ref::<Counter>().unwrap()
+    }
+
+    #[test]
+    fn native_counter() {
+        let mut state = State::new().with_cwd();
+        let ty = state.make_native_type(
+            "Counter",
+            vec![
+                (
+                    "incr",
+                    Function::from_native("", |c: Native| {
+                        let c = counter(&c);
+                        c.0.set(c.0.get() + 1);
+                        Ok(())
+                    }),
+                ),
+                (
+                    "get",
+                    Function::from_native("", |c: Native| Ok(counter(&c).0.get())),
+                ),
+            ],
+        );
+        let c = ty.wrap(Counter(Cell::new(0)));
+        state.storage.insert("c", c.clone());
+        state.storage.insert("d", ty.wrap(Counter(Cell::new(0))));
+
+        state.eval_more("@(c, incr), @(c, incr)").unwrap();
+        assert_eq!(state.eval_more("@(c, get)").unwrap(), Atom::Int(2));
+        assert_eq!(
+            state
+                .eval_more("printable(c)")
+                .unwrap()
+                .as_string()
+                .unwrap(),
+            "<counter at 2>"
+        );
+        assert_eq!(state.eval_more("==(c, c)").unwrap(), Atom::Bool(true));
+        assert_eq!(state.eval_more("==(c, d)").unwrap(), Atom::Bool(false));
+        assert_eq!(
+            state.eval_more("type_id(c)").unwrap(),
+            Atom::Int(ty.ty_id())
+        );
+        assert_eq!(
+            state.eval_more("@(c, reset)").unwrap_err().msg,
+            "native value of type `Counter` has no method `reset`"
+        );
+        assert_eq!(counter(&c.native().unwrap()).0.get(), 2);
+    }
+}
@@ -47,7 +47,7 @@ fn optimize(program: &mut Argument, data: &mut OptData) -> Unit {
 
 fn inline_trivial_underscore_call(call_arg: &mut Argument) -> Unit {
     let call = call_arg.call()?;
-    if call.name == "_" {
+    if &*call.name == "_" {
         if call.args.is_empty() {
             *call_arg = Argument::Atom(Atom::Null, call_arg.span().clone());
         } else if call.args.len() == 1 {
@@ -1,7 +1,8 @@
 // The grammar is:
-// G = ({S, X}, {a, n, (, ), ","}, P, S)
+// G = ({R, S, X}, {a, n, (, ), ","}, P, R)
 // (a represents any atom, n any name / ident)
 // with P:
+//  R -> X | ε
 //  S -> a | n | n() | n(X)
 //  X -> S,X | S, | S
 
@@ -12,21 +13,41 @@ use crate::parsing::token::Token;
 use crate::prelude::*;
 use crate::{exception::SyntaxError, no_path};
 pub use positions::{Position, Span};
+use std::path::PathBuf;
+use std::rc::Rc;
+pub(crate) use token::extract;
 pub(crate) use token::{TokenData, tokenize};
 
 fn syntax_error<T>(msg: impl Into<String>, span: &Span) -> Result<T> {
     Err(Exception::spanned(SyntaxError, msg, span))
 }
 
-pub fn build_program(tokens: Vec<Token>) -> Result<Argument> {
+/// Builds a program from the tokens of its top-level arguments.
+///
+/// The arguments are wrapped into the implicit `_` call that imports the prelude first,
+/// which is built directly instead of being part of the source code,
+/// so the spans of the program match the code exactly.
+pub fn build_program(tokens: Vec<Token>, file_path: Rc<PathBuf>) -> Result<Argument> {
     let mut cursor = tokens.as_slice();
-    let arg = build_subprogram(&mut cursor)?;
-
-    if let Some(t) = without_comments(cursor).next() {
-        return syntax_error("trailing unparsed tokens detected", &t.span);
-    }
+    let mut args = vec![Argument::FunctionCall(
+        FunctionCall {
+            args: vec![],
+            name: "__builtin_prelude_import".into(),
+            doc_comment: String::new(),
+        },
+        Span::single(Position::ONE, Rc::clone(&file_path)),
+    )];
+    build_arguments(&mut cursor, &mut args)?;
 
-    Ok(arg)
+    let end = tokens.last().map_or(Position::ONE, |t| t.span.end);
+    Ok(Argument::FunctionCall(
+        FunctionCall {
+            args,
+            name: "_".into(),
+            doc_comment: String::new(),
+        },
+        Span::new(Position::ONE, end, file_path),
+    ))
 }
 
 fn without_comments(tokens: &[Token]) -> impl DoubleEndedIterator<Item = &Token> {
@@ -44,9 +65,8 @@ fn eat_commented_token<'a>(tokens: &mut &'a [Token]) -> Result<(&'a [Token], &'a
         }
     }
 
-    // this error can only be caused in two ways:
-    // * when parsing an argument list comma, but then the error message is discarded
-    // * by parsing a program with zero non-comment tokens, so a unhelpful span is fine
+    // this error can only be caused when parsing an argument list comma,
+    // but then the error message is discarded
     Err(Exception::spanned(
         SyntaxError,
         "program contains no non-comment tokens",
@@ -110,24 +130,13 @@ fn build_subprogram(tokens: &mut &[Token]) -> Result<Argument> {
     {
         let (right_paren_span, rest) = extract_within_parens(tokens)?;
         let mut args = vec![];
-
-        while without_comments(tokens).next().is_some() {
-            args.push(build_subprogram(tokens)?);
-
-            let Ok((_, comma)) = eat_commented_token(tokens) else {
-                break;
-            };
-
-            if !comma.is_comma() {
-                return syntax_error("missing comma in argument list", &comma.span);
-            }
-        }
+        build_arguments(tokens, &mut args)?;
         *tokens = rest;
 
         Ok(Argument::FunctionCall(
             FunctionCall {
                 args,
-                name,
+                name: name.into(),
                 doc_comment: concat_doc_comments(doc_comments),
             },
             Span {
@@ -140,49 +149,121 @@ fn build_subprogram(tokens: &mut &[Token]) -> Result<Argument> {
     }
 }
 
+/// Builds a comma separated list of arguments (with an optional trailing comma)
+/// from all of the given tokens and appends them to `args`.
+fn build_arguments(tokens: &mut &[Token], args: &mut Vec<Argument>) -> Result<()> {
+    while let Some(first) = without_comments(tokens).next() {
+        // a `(` without a function name is reported as unclosed if nothing closes it,
+        // since that is the more likely mistake
+        if first.is_left_paren() {
+            let start = tokens.iter().position(|t| !t.is_comment()).unwrap();
+            extract_within_parens(&mut &tokens[start..])?;
+        }
+        args.push(build_subprogram(tokens)?);
+
+        let Ok((_, comma)) = eat_commented_token(tokens) else {
+            break;
+        };
+
+        if comma.is_right_paren() {
+            return comma.unmatched_paren();
+        }
+        if !comma.is_comma() {
+            return syntax_error("missing comma in argument list", &comma.span);
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::no_path;
 
+    fn parse(code: &str) -> Result<Argument> {
+        build_program(tokenize(code, no_path())?, no_path())
+    }
+
+    /// Returns the top-level arguments of the program, without the prelude import.
+    fn top_level(program: &Argument) -> &[Argument] {
+        let Argument::FunctionCall(call, _) = program else {
+            unreachable!()
+        };
+        &call.args[1..]
+    }
+
     #[test]
     fn extra_parens() {
-        let prog = build_program(tokenize("_((2))", no_path()).unwrap());
+        let prog = parse("_((2))");
 
         assert_eq!(
             prog.unwrap_err().to_string(),
-            "SyntaxError: expected atom or ident\nat <file>:0:3"
+            "SyntaxError: expected atom or ident\nat <file>:1:3"
         );
 
-        let prog = build_program(tokenize("(print(2)), print(3)", no_path()).unwrap());
+        let prog = parse("(print(2)), print(3)");
 
         assert_eq!(
             prog.unwrap_err().to_string(),
-            "SyntaxError: expected atom or ident\nat <file>:0:1"
+            "SyntaxError: expected atom or ident\nat <file>:1:1"
+        );
+    }
+
+    #[test]
+    fn unclosed_paren_without_name() {
+        let prog = parse("print(1),\n# comment\n(print(2)");
+        assert_eq!(
+            prog.unwrap_err().to_string(),
+            "SyntaxError: unclosed `(` parenthesis\nat <file>:3:1"
         );
     }
 
     #[test]
     fn atom_fn() {
-        let prog = build_program(tokenize("2(4)", no_path()).unwrap());
-        assert_eq!(prog.unwrap().stringify(), "2(4)");
+        let prog = parse("2(4)").unwrap();
+        assert_eq!(top_level(&prog)[0].stringify(), "2(4)");
     }
 
     #[test]
     fn two_commas() {
-        let prog = build_program(tokenize("_(4,,4)", no_path()).unwrap());
+        let prog = parse("_(4,,4)");
         assert_eq!(
             prog.unwrap_err().to_string(),
-            "SyntaxError: expected atom or ident\nat <file>:0:5"
+            "SyntaxError: expected atom or ident\nat <file>:1:5"
         );
     }
 
     #[test]
-    fn empty_program() {
-        let prog = build_program(tokenize("", no_path()).unwrap());
+    fn missing_comma() {
+        let prog = parse("print(1)\nprint(2)");
+        assert_eq!(
+            prog.unwrap_err().to_string(),
+            "SyntaxError: missing comma in argument list\nat <file>:2:1"
+        );
+    }
+
+    #[test]
+    fn unmatched_paren() {
+        let prog = parse("print(2)),\n(1");
         assert_eq!(
             prog.unwrap_err().to_string(),
-            "SyntaxError: program contains no non-comment tokens\nat <file>:1:1"
+            "SyntaxError: unmatched `)` parenthesis\nat <file>:1:9"
         );
     }
+
+    #[test]
+    fn empty_program() {
+        let prog = parse("# only a comment").unwrap();
+        assert_eq!(prog.stringify(), "_(__builtin_prelude_import())");
+    }
+
+    #[test]
+    fn top_level_spans() {
+        let prog = parse("print(1),\n\tx,").unwrap();
+        let spans = top_level(&prog)
+            .iter()
+            .map(|arg| arg.span().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(spans, ["<file>:1:6", "<file>:2:2"]);
+    }
 }
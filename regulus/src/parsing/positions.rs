@@ -117,7 +117,7 @@ impl<'a> CharPositions<'a> {
     pub fn new(text: &'a str) -> Self {
         Self {
             text: text.chars(),
-            pos: Position { line: 0, column: 1 },
+            pos: Position::ONE,
         }
     }
 }
@@ -133,7 +133,7 @@ mod tests {
     }
 
     const fn p(line: u32, column: u32) -> Position {
-        Position::new(line - 1, column)
+        Position::new(line, column)
     }
 
     const fn pc(line: u32, column: u32, ch: char) -> (Position, char) {
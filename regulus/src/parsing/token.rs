@@ -28,13 +28,27 @@ impl Token {
     }
 
     pub fn to_name(&self) -> Result<String> {
-        if let TokenData::Name(name) = &self.data {
-            Ok(name.clone())
-        } else {
-            syntax_error("expected atom or ident", &self.span)
+        match &self.data {
+            TokenData::Name(name) => Ok(name.clone()),
+            TokenData::RightParen => self.unmatched_paren(),
+            _ => syntax_error("expected atom or ident", &self.span),
         }
     }
 
+    /// Raises the error for a `)` that does not close any `(`.
+    /// The parser only ever sees such parentheses outside of every argument list.
+    pub fn unmatched_paren<T>(&self) -> Result<T> {
+        syntax_error("unmatched `)` parenthesis", &self.span)
+    }
+
+    pub const fn is_left_paren(&self) -> bool {
+        matches!(self.data, TokenData::LeftParen)
+    }
+
+    pub const fn is_right_paren(&self) -> bool {
+        matches!(self.data, TokenData::RightParen)
+    }
+
     pub const fn is_comma(&self) -> bool {
         matches!(self.data, TokenData::Comma)
     }
@@ -196,8 +210,6 @@ fn last_pos(code: &str) -> Position {
 
 /// Returns all characters of the text that the given span encloses.
 /// Returns `None` if the span is invalid (end before start or out of bounds).
-// TODO: use or remove
-#[cfg_attr(not(test), expect(dead_code))]
 pub fn extract(text: &str, span: &Span) -> Option<String> {
     let mut start_found = false;
 
@@ -227,8 +239,8 @@ mod tests {
 
     fn sp(start_line: u32, start_col: u32, end_line: u32, end_col: u32) -> Span {
         Span::new(
-            Position::new(start_line - 1, start_col),
-            Position::new(end_line - 1, end_col),
+            Position::new(start_line, start_col),
+            Position::new(end_line, end_col),
             no_path(),
         )
     }
@@ -0,0 +1,239 @@
+//! Rendering exceptions together with the source code they point to.
+
+use crate::interned_stdlib::INTERNED_STL;
+use crate::parsing::extract;
+use crate::prelude::*;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const BLUE: &str = "\x1b[34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Renders exceptions with the line of source code of every backtrace entry,
+/// underlining the part of the line the entry points to.
+///
+/// The source code of files is read from the file system and that of STL modules
+/// (shown as `<stl:name>`) is taken from the interned STL.
+/// Code that is not available in either place, such as the chunks of
+/// [`State::eval_more`], can be added with [`add_source`](Self::add_source).
+/// Entries whose source cannot be found are shown without code.
+///
+/// The [`Display`](std::fmt::Display) implementation of [`Exception`] gives the plain format
+/// without any source code instead.
+///
+/// # Example
+/// ```rust
+/// use regulus::prelude::*;
+/// let mut state = State::new();
+/// let error = state.eval_more("=(x, missing)").unwrap_err();
+///
+/// let mut renderer = ErrorRenderer::new();
+/// renderer.add_source("<chunk:1>", "=(x, missing)");
+/// assert_eq!(
+///     renderer.render(&error),
+///     "NameError: No variable named `missing` found!
+/// at <chunk:1>:1:6
+/// 1 | =(x, missing)
+///   |      ^^^^^^^
+/// at = (<chunk:1>:1:2)
+/// 1 | =(x, missing)
+///   |  ^^^^^^^^^^^^"
+/// );
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ErrorRenderer {
+    colored: bool,
+    sources: HashMap<PathBuf, String>,
+}
+
+impl ErrorRenderer {
+    /// Creates a renderer that does not use colors.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether the output is highlighted with ANSI color codes.
+    #[must_use]
+    pub const fn colored(mut self, colored: bool) -> Self {
+        self.colored = colored;
+        self
+    }
+
+    /// Adds the source code of the program at the given path, as it was passed to the state.
+    pub fn add_source(&mut self, path: impl Into<PathBuf>, code: impl Into<String>) {
+        self.sources.insert(path.into(), code.into());
+    }
+
+    /// Renders the exception, including the exceptions that caused it.
+    pub fn render(&self, exception: &Exception) -> String {
+        let mut loaded = HashMap::new();
+        let mut out = String::new();
+        self.render_into(exception, &mut loaded, &mut out);
+        out
+    }
+
+    fn render_into(
+        &self,
+        exception: &Exception,
+        loaded: &mut HashMap<PathBuf, Option<String>>,
+        out: &mut String,
+    ) {
+        let _ = write!(
+            out,
+            "{}{}{}Error{}: {}",
+            self.color(BOLD),
+            self.color(RED),
+            exception.error,
+            self.color(RESET),
+            exception.message()
+        );
+        for shown in exception.collapsed_backtrace() {
+            let ShownFrame::Frame(frame) = shown else {
+                let _ = write!(out, "\n{}{shown}{}", self.color(BLUE), self.color(RESET));
+                continue;
+            };
+            let _ = write!(out, "\n{}at{} {frame}", self.color(BLUE), self.color(RESET));
+            let span = &frame.span;
+            let source = loaded
+                .entry(span.file.to_path_buf())
+                .or_insert_with(|| self.load_source(&span.file));
+            if let Some(source) = source {
+                self.render_snippet(source, span, RED, out);
+            }
+        }
+        if let Some(cause) = &exception.cause {
+            let _ = write!(out, "\ncaused by: ");
+            self.render_into(cause, loaded, out);
+        }
+    }
+
+    /// Renders a diagnostic the same way as an exception, with the code it is about.
+    pub fn render_diagnostic(&self, diagnostic: &Diagnostic) -> String {
+        let color = match diagnostic.severity {
+            Severity::Warning => YELLOW,
+        };
+        let mut out = format!(
+            "{}{}{}{}: {}\n{}at{} {}",
+            self.color(BOLD),
+            self.color(color),
+            diagnostic.severity,
+            self.color(RESET),
+            diagnostic.msg,
+            self.color(BLUE),
+            self.color(RESET),
+            diagnostic.span
+        );
+        if let Some(source) = self.load_source(&diagnostic.span.file) {
+            self.render_snippet(&source, &diagnostic.span, color, &mut out);
+        }
+        out
+    }
+
+    /// Writes the line the span starts in and underlines the span with the given color.
+    /// Spans covering multiple lines are underlined until the end of their first line.
+    fn render_snippet(&self, source: &str, span: &Span, underline: &'static str, out: &mut String) {
+        let Some(line) =
+            (span.start.line.checked_sub(1)).and_then(|index| source.lines().nth(index as usize))
+        else {
+            return;
+        };
+        let indent = line
+            .chars()
+            .take(span.start.column.saturating_sub(1) as usize)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+        let underline_len = extract(source, span)
+            .map_or(1, |text| text.chars().take_while(|&c| c != '\n').count())
+            .max(1);
+
+        let line_number = span.start.line.to_string();
+        let gutter = " ".repeat(line_number.len());
+        let (blue, underline, reset) = (self.color(BLUE), self.color(underline), self.color(RESET));
+        let _ = write!(
+            out,
+            "\n{blue}{line_number} |{reset} {line}\n{blue}{gutter} |{reset} {indent}{underline}{}{reset}",
+            "^".repeat(underline_len)
+        );
+    }
+
+    /// Returns the source code of the file with the given path.
+    fn load_source(&self, path: &Path) -> Option<String> {
+        if let Some(code) = self.sources.get(path) {
+            Some(code.clone())
+        } else if let Some(name) = path
+            .to_str()
+            .and_then(|path| path.strip_prefix("<stl:")?.strip_suffix('>'))
+        {
+            Some((*INTERNED_STL.get(name)?).to_string())
+        } else {
+            fs::read_to_string(path).ok()
+        }
+    }
+
+    const fn color(&self, code: &'static str) -> &'static str {
+        if self.colored { code } else { "" }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snippets() {
+        let code = "def(f, x, _(\n\terror(\"Custom\",\n  \"failed\")\n)),\nf(1)";
+        let mut state = State::new();
+        let error = state.eval_more(code).unwrap_err();
+        let mut renderer = ErrorRenderer::new();
+        renderer.add_source("<chunk:1>", code);
+        assert_eq!(
+            renderer.render(&error),
+            "CustomError: failed
+at error (<chunk:1>:2:7)
+2 | \terror(\"Custom\",
+  | \t     ^^^^^^^^^^
+at _ (<chunk:1>:1:12)
+1 | def(f, x, _(
+  |            ^
+at f (<chunk:1>:5:2)
+5 | f(1)
+  |  ^^^"
+        );
+
+        // sources that cannot be found are left out
+        assert_eq!(ErrorRenderer::new().render(&error), error.to_string());
+    }
+
+    #[test]
+    fn colors_and_causes() {
+        let mut state = State::new().with_cwd();
+        let error = state.eval_more("int(\"x\")").unwrap_err();
+        let rendered = ErrorRenderer::new().colored(true).render(&error);
+        assert!(rendered.starts_with("\x1b[1m\x1b[31mValueError\x1b[0m: cannot cast x to int"));
+        // the STL source is shown
+        assert!(rendered.contains("error(\"Value\", strconcat(\"cannot cast \""));
+        assert!(rendered.contains("caused by: \x1b[1m\x1b[31mValueError"));
+    }
+
+    #[test]
+    fn diagnostics() {
+        let code = "=(__stl_x,\n  1)";
+        let mut state = State::new();
+        state.eval_more(code).unwrap();
+        let mut renderer = ErrorRenderer::new();
+        renderer.add_source("<chunk:1>", code);
+        let diagnostics = state.take_diagnostics();
+        assert_eq!(
+            renderer.render_diagnostic(&diagnostics[0]),
+            "warning: `__stl_x` is reserved for the STL and may be overwritten by it
+at <chunk:1>:1:3
+1 | =(__stl_x,
+  |   ^^^^^^^"
+        );
+    }
+}
@@ -0,0 +1,179 @@
+//! Finding the source code of modules for `import`.
+
+use crate::prelude::*;
+use crate::state::Directory;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The source code of a module found by an [`ImportResolver`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedImport {
+    /// The source code of the module.
+    pub code: String,
+    /// The path shown in backtraces.
+    /// It is also used to detect cyclic imports, so it should be unique for every module.
+    pub path: PathBuf,
+    /// The directory that imports inside of the module are resolved relative to,
+    /// if it was read from the file system.
+    pub directory: Option<PathBuf>,
+}
+
+/// Finds the source code of a module by its name.
+///
+/// Set a resolver with [`State::with_import_resolver`].
+/// It is used for all imports outside of the STL itself, including those in imported modules.
+/// Modules it does not find are looked up in the STL afterwards.
+pub trait ImportResolver {
+    /// Returns the module with the given name,
+    /// or `None` if it cannot be found by this resolver.
+    ///
+    /// The state is the one of the importing program.
+    fn resolve(&self, name: &str, state: &State) -> Result<Option<ResolvedImport>>;
+}
+
+/// The default resolver, which looks for a file called `{name}.re` in the directory of the
+/// importing program.
+///
+/// It respects the [`fs_import`](crate::prelude::Capabilities::fs_import) capability.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FsResolver;
+
+impl ImportResolver for FsResolver {
+    fn resolve(&self, name: &str, state: &State) -> Result<Option<ResolvedImport>> {
+        let Directory::Regular(dir_path) = &state.file_directory else {
+            return Ok(None);
+        };
+        if !state.capabilities.can_import_from(dir_path) {
+            return Ok(None);
+        }
+        let Some(path) = find_in_dir(state, name, dir_path)? else {
+            return Ok(None);
+        };
+        let code = fs::read_to_string(&path).map_err(|err| {
+            state.raise(
+                "Import",
+                format!("error when reading file `{}`: {err}", path.display()),
+            )
+        })?;
+        Ok(Some(ResolvedImport {
+            code,
+            directory: Some(dir_path.clone()),
+            path,
+        }))
+    }
+}
+
+/// Returns:
+/// * `Ok(None)` if the resolution in the given directory failed
+/// * `Ok(Some(path))` if the code was found at `path` in the given directory
+/// * `Err(error)` if reading the directory failed
+fn find_in_dir(state: &State, name: &str, dir_path: &Path) -> Result<Option<PathBuf>> {
+    let paths = fs::read_dir(dir_path)
+        .map_err(|err| {
+            state.raise(
+                "Import",
+                format!(
+                    "error when reading directory `{}`: {err}",
+                    dir_path.display()
+                ),
+            )
+        })?
+        .flatten();
+    for item in paths {
+        if *item.file_name() == *format!("{name}.{FILE_EXTENSION}") {
+            return Ok(Some(item.path()));
+        }
+    }
+    Ok(None)
+}
+
+/// A resolver for modules kept in memory, mapping module names to their source code.
+///
+/// The modules are shown as `<module:{name}>` in backtraces.
+///
+/// # Example
+/// ```rust
+/// use regulus::prelude::*;
+/// let resolver = MemoryResolver::from([("config", "=(answer, 42)")]);
+/// let mut state = State::new()
+///     .with_import_resolver(resolver)
+///     .with_code("import(config), answer");
+/// assert_eq!(state.run().unwrap(), Atom::Int(42));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MemoryResolver {
+    modules: HashMap<String, String>,
+}
+
+impl MemoryResolver {
+    /// Creates a resolver without any modules.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a module with the given name and source code, replacing any previous one.
+    pub fn insert(&mut self, name: impl Into<String>, code: impl Into<String>) {
+        self.modules.insert(name.into(), code.into());
+    }
+}
+
+impl<N: Into<String>, C: Into<String>, const LEN: usize> From<[(N, C); LEN]> for MemoryResolver {
+    fn from(modules: [(N, C); LEN]) -> Self {
+        Self {
+            modules: modules
+                .into_iter()
+                .map(|(name, code)| (name.into(), code.into()))
+                .collect(),
+        }
+    }
+}
+
+impl ImportResolver for MemoryResolver {
+    fn resolve(&self, name: &str, _: &State) -> Result<Option<ResolvedImport>> {
+        Ok(self.modules.get(name).map(|code| ResolvedImport {
+            code: code.clone(),
+            path: PathBuf::from(format!("<module:{name}>")),
+            directory: None,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_modules() {
+        let resolver = MemoryResolver::from([
+            (
+                "shapes",
+                "import(geometry), def(square_area, x, area(x, x))",
+            ),
+            ("geometry", "def(area, w, h, *(w, h))"),
+            // shadows the STL module, but not for the STL itself
+            ("range", "=(shadowed, true)"),
+            ("cycle_a", "import(cycle_b)"),
+            ("cycle_b", "import(cycle_a)"),
+        ]);
+        let mut state = State::new().with_cwd().with_import_resolver(resolver);
+        assert_eq!(
+            state.eval_more("import(shapes), square_area(3)").unwrap(),
+            Atom::Int(9)
+        );
+        assert_eq!(
+            state.eval_more("import(range), shadowed").unwrap(),
+            Atom::Bool(true)
+        );
+        // `print` imports the real `range` module internally
+        assert_eq!(state.eval_more("print(1)").unwrap(), Atom::Null);
+        assert_eq!(
+            state.eval_more("import(cycle_a)").unwrap_err().msg,
+            "cyclic import of `cycle_a` at path `<module:cycle_a>` detected"
+        );
+        assert_eq!(
+            state.eval_more("import(missing)").unwrap_err().msg,
+            "failed to find file for importing `missing`"
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use std::path::{Path, PathBuf};
+
+/// Which directories `import` may read modules from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsImport {
+    /// Modules may be imported from any directory.
+    Any,
+    /// Modules may only be imported from the given directories and their subdirectories.
+    Only(Vec<PathBuf>),
+    /// Only the STL can be imported.
+    Denied,
+}
+
+/// The set of operations a program is allowed to perform.
+///
+/// Using a denied capability raises a `PermissionError`.
+/// By default, everything is allowed; use [`Capabilities::none`] as a starting point
+/// for running untrusted code.
+///
+/// # Example
+/// ```rust
+/// use regulus::prelude::*;
+/// let caps = Capabilities {
+///     stdout: true,
+///     ..Capabilities::none()
+/// };
+/// let mut state = State::new().with_capabilities(caps).with_code("eval(\"1\")");
+/// assert_eq!(state.run().unwrap_err().error, "Permission");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[expect(
+    clippy::struct_excessive_bools,
+    reason = "each capability is independent of the others"
+)]
+pub struct Capabilities {
+    /// Importing modules from the file system.
+    pub fs_import: FsImport,
+    /// Reading from stdin with `input`.
+    pub stdin: bool,
+    /// Writing to stdout with `write` and `print`.
+    pub stdout: bool,
+    /// Writing to stderr with `write_err`.
+    pub stderr: bool,
+    /// Reading the current time.
+    /// Without it, the RNG of the `random` module starts with a fixed seed.
+    pub clock: bool,
+    /// Running code from strings with `eval`.
+    pub eval: bool,
+}
+
+impl Capabilities {
+    /// Allows every operation.
+    pub const fn all() -> Self {
+        Self {
+            fs_import: FsImport::Any,
+            stdin: true,
+            stdout: true,
+            stderr: true,
+            clock: true,
+            eval: true,
+        }
+    }
+
+    /// Denies every operation.
+    pub const fn none() -> Self {
+        Self {
+            fs_import: FsImport::Denied,
+            stdin: false,
+            stdout: false,
+            stderr: false,
+            clock: false,
+            eval: false,
+        }
+    }
+
+    /// Returns whether modules may be imported from the given directory.
+    pub(crate) fn can_import_from(&self, dir: &Path) -> bool {
+        match &self.fs_import {
+            FsImport::Any => true,
+            FsImport::Only(allowed) => {
+                // compare canonical paths so that `..` cannot be used to escape
+                let Ok(dir) = dir.canonicalize() else {
+                    return false;
+                };
+                allowed.iter().any(|allowed| {
+                    allowed
+                        .canonicalize()
+                        .is_ok_and(|allowed| dir.starts_with(allowed))
+                })
+            }
+            FsImport::Denied => false,
+        }
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self::all()
+    }
+}
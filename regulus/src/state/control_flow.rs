@@ -0,0 +1,122 @@
+use crate::exception::ArgumentError;
+use crate::prelude::*;
+
+/// The function call and loop that code is currently running in.
+///
+/// `return`, `break` and `continue` use it to find the function or loop they refer to,
+/// even if they are part of a lazy argument that is evaluated by another function.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Frame {
+    /// A unique id of the current function call, `0` outside of functions.
+    pub(crate) id: u64,
+    /// How many loops of the current function call are running.
+    pub(crate) loop_depth: usize,
+}
+
+/// A `return`, `break` or `continue` that is unwinding the stack until it reaches the function
+/// call or loop it refers to.
+///
+/// Just like `exit`, every evaluation returns `null` immediately while a signal is active.
+#[derive(Debug, Clone)]
+pub(crate) enum Signal {
+    Return(Atom, Frame),
+    Break(Frame),
+    Continue(Frame),
+}
+
+impl Signal {
+    const fn name(&self) -> &'static str {
+        match self {
+            Self::Return(..) => "return",
+            Self::Break(_) => "break",
+            Self::Continue(_) => "continue",
+        }
+    }
+}
+
+impl State {
+    /// Starts a new function call and returns the frame of the caller,
+    /// which must be passed to [`leave_function`](Self::leave_function) afterwards.
+    pub(crate) const fn enter_function(&mut self) -> Frame {
+        self.next_frame_id += 1;
+        let new_frame = Frame {
+            id: self.next_frame_id,
+            loop_depth: 0,
+        };
+        std::mem::replace(&mut self.frame, new_frame)
+    }
+
+    /// Ends the current function call, stopping a `return` that refers to it.
+    pub(crate) fn leave_function(&mut self, caller: Frame, result: Result<Atom>) -> Result<Atom> {
+        let frame = std::mem::replace(&mut self.frame, caller);
+        match self.signal.take() {
+            Some(Signal::Return(value, target)) if target.id == frame.id => Ok(value),
+            // errors caused by unwinding are not relevant
+            Some(signal) => {
+                self.signal = Some(signal);
+                Ok(Atom::Null)
+            }
+            None => result,
+        }
+    }
+
+    /// Evaluates the body of a loop once, stopping a `break` or `continue` that refers to it.
+    ///
+    /// Returns whether the loop should keep running.
+    pub(crate) fn eval_loop_body(&mut self, body: &Argument) -> Result<bool> {
+        self.frame.loop_depth += 1;
+        let frame = self.frame;
+        let result = body.eval(self).map(drop);
+        self.frame.loop_depth -= 1;
+        match self.signal.take() {
+            Some(Signal::Break(target)) if target == frame => Ok(false),
+            Some(Signal::Continue(target)) if target == frame => Ok(true),
+            Some(signal) => {
+                self.signal = Some(signal);
+                Ok(false)
+            }
+            None => result.map(|()| self.exit_unwind_value.is_none()),
+        }
+    }
+
+    /// Runs cleanup code after the given result was produced, even if an exception was raised
+    /// or the program is unwinding because of `exit`, `return`, `break` or `continue`.
+    ///
+    /// The result is kept unless the cleanup raises an exception itself,
+    /// which then replaces the result and stops the unwinding.
+    pub(crate) fn run_cleanup(
+        &mut self,
+        result: Result<Atom>,
+        cleanup: impl FnOnce(&mut Self) -> Result<()>,
+    ) -> Result<Atom> {
+        let exit_unwind_value = self.exit_unwind_value.take();
+        let signal = self.signal.take();
+        let cleanup_result = cleanup(self);
+        // the cleanup may start unwinding itself, which then takes precedence
+        let is_unwinding = self.exit_unwind_value.is_some() || self.signal.is_some();
+        match cleanup_result {
+            Err(exc) if !is_unwinding => Err(exc),
+            _ => {
+                if !is_unwinding {
+                    self.exit_unwind_value = exit_unwind_value;
+                    self.signal = signal;
+                }
+                result
+            }
+        }
+    }
+
+    /// Raises an exception if a signal is still active after running code,
+    /// which happens if it was used after its function call or loop already ended.
+    pub(crate) fn check_stray_signal(&mut self) -> Result<()> {
+        if let Some(signal) = self.signal.take() {
+            raise!(
+                self,
+                ArgumentError,
+                "`{}` was used after its function or loop already ended",
+                signal.name()
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,162 @@
+use crate::builtins::builtins;
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::is_reserved_name;
+use crate::prelude::*;
+
+impl State {
+    /// Returns the diagnostics collected so far,
+    /// including those of the modules the program imported.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.diagnostics.borrow().clone()
+    }
+
+    /// Returns the diagnostics collected so far and removes them from the state,
+    /// so that later calls only return new diagnostics.
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        self.diagnostics.take()
+    }
+
+    /// Adds a warning about the given code.
+    ///
+    /// The STL is trusted, so warnings about its code are left out.
+    /// Warnings that were already added for the same code are left out as well,
+    /// for example when a function containing the code is called repeatedly.
+    pub(crate) fn warn(&self, msg: impl Into<String>, span: &Span) {
+        if self.is_stl_module {
+            return;
+        }
+        let diagnostic = Diagnostic::new(Severity::Warning, msg, span.clone());
+        let mut diagnostics = self.diagnostics.borrow_mut();
+        if !diagnostics.contains(&diagnostic) {
+            diagnostics.push(diagnostic);
+        }
+    }
+
+    /// Warns about problems that can be seen in the parsed code before it runs,
+    /// which are definitions of reserved names with `def` or `=`.
+    ///
+    /// These are reported even if the code never runs.
+    pub(crate) fn check_parsed(&self, argument: &Argument) {
+        let Argument::FunctionCall(call, _) = argument else {
+            return;
+        };
+        if matches!(&*call.name, "def" | "=")
+            && let Some(Argument::Variable(name, span)) = call.args.first()
+        {
+            self.check_reserved(name, span);
+        }
+        for arg in &call.args {
+            self.check_parsed(arg);
+        }
+    }
+
+    fn check_reserved(&self, name: &str, span: &Span) {
+        if is_reserved_name(name) {
+            self.warn(
+                format!("`{name}` is reserved for the STL and may be overwritten by it"),
+                span,
+            );
+        }
+    }
+
+    /// Warns about definitions of names that may cause problems,
+    /// which are reserved names and builtins that optimizations may rely on.
+    ///
+    /// Reserved names are checked again here, since they may also be defined through aliases
+    /// like `assign`, which [`check_parsed`](Self::check_parsed) does not know about.
+    pub(crate) fn check_definition(&self, name: &str, span: &Span) {
+        if is_reserved_name(name) {
+            self.check_reserved(name, span);
+        } else if self.optimizations_enabled
+            && self.storage.foreign.contains(name)
+            && builtins().contains_key(name)
+        {
+            self.warn(
+                format!(
+                    "redefining the builtin `{name}` may change the behavior of the program while optimizations are enabled"
+                ),
+                span,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(state: &mut State) -> Vec<String> {
+        state
+            .take_diagnostics()
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn reserved_names() {
+        let mut state = State::new().with_cwd();
+        state.eval_more("=(__builtin_x, 1), =(x, 2)").unwrap();
+        // the prelude defines reserved names, but the STL is trusted
+        assert_eq!(
+            messages(&mut state),
+            [
+                "warning: `__builtin_x` is reserved for the STL and may be overwritten by it\nat <chunk:1>:1:3"
+            ]
+        );
+        // the same definition running again is only reported once
+        state
+            .eval_more("def(f, def(__stl_y, 1)), f(), f()")
+            .unwrap();
+        assert_eq!(messages(&mut state).len(), 1);
+        assert!(messages(&mut state).is_empty());
+        // definitions are already reported when the code is parsed
+        state.eval_more("def(g, =(__stl_z, 1))").unwrap();
+        assert_eq!(
+            messages(&mut state),
+            [
+                "warning: `__stl_z` is reserved for the STL and may be overwritten by it\nat <chunk:3>:1:10"
+            ]
+        );
+        state
+            .eval_more("ifelse(true, 1, =(__builtin_z, 1))")
+            .unwrap();
+        assert_eq!(messages(&mut state).len(), 1);
+        // aliases are only seen when they run
+        state
+            .eval_more("import(aliases), assign(__stl_w, 1)")
+            .unwrap();
+        assert_eq!(messages(&mut state).len(), 1);
+    }
+
+    #[test]
+    fn redefined_builtins() {
+        let mut state = State::new().with_cwd();
+        state.eval_more("def(_, x, x), =(print, 1)").unwrap();
+        assert!(state.diagnostics().is_empty());
+
+        let mut state = State::new().with_cwd().enable_optimizations();
+        state.eval_more("def(_, x, x), =(print, 1)").unwrap();
+        assert_eq!(
+            messages(&mut state),
+            [
+                "warning: redefining the builtin `_` may change the behavior of the program while optimizations are enabled\nat <chunk:1>:1:5"
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicate_imports() {
+        let mut state = State::new().with_cwd();
+        state.eval_more("import(sorting), import(math)").unwrap();
+        assert!(state.diagnostics().is_empty());
+        state.eval_more("import(sorting)").unwrap();
+        assert_eq!(
+            messages(&mut state),
+            ["warning: `sorting` was already imported at <chunk:1>:1:8\nat <chunk:2>:1:8"]
+        );
+        // calling the same import again is fine
+        state.eval_more("def(f, import(range)), f(), f()").unwrap();
+        assert!(state.diagnostics().is_empty());
+    }
+}
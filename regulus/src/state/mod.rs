@@ -1,19 +1,44 @@
+mod capabilities;
+mod control_flow;
+mod diagnostics;
 mod storage;
+mod suggestions;
 
+use crate::exception::{
+    InterruptedError, NameError, PermissionError, RecursionError, ResourceError, TimeoutError,
+};
+use crate::function::host_span;
 use crate::no_path;
 use crate::optimizations::run_optimizations;
 use crate::parsing::{build_program, tokenize};
 use crate::prelude::*;
+pub use capabilities::{Capabilities, FsImport};
+pub(crate) use control_flow::{Frame, Signal};
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::io::{BufRead, BufReader, Read, Write, stderr, stdin, stdout};
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
 use std::{env, fs, io};
 pub use storage::Storage;
+pub(crate) use storage::{LoadedModule, ModuleEnv, SharedValue};
+pub(crate) use suggestions::UnknownName;
+
+thread_local! {
+    /// The parsed programs of all STL modules that were run so far,
+    /// by their path and whether optimizations were enabled.
+    static PARSED_STL: RefCell<HashMap<(PathBuf, bool), Rc<Argument>>> = RefCell::default();
+}
 
 #[derive(Clone)]
 pub(crate) enum Directory {
     Regular(PathBuf),
-    FromEval,
+    /// Code that was not read from a file, such as the argument of `eval`.
+    Virtual,
     /// Should only be used internally.
     InternedSTL,
 }
@@ -33,17 +58,61 @@ pub struct State {
     pub(crate) file_directory: Directory,
     pub(crate) current_file_path: Option<PathBuf>,
     pub(crate) exit_unwind_value: Option<Result<Atom>>,
-    pub(crate) backtrace: Vec<Span>,
+    /// The active `return`, `break` or `continue`, if any.
+    pub(crate) signal: Option<Signal>,
+    pub(crate) frame: Frame,
+    next_frame_id: u64,
+    pub(crate) backtrace: Vec<BacktraceFrame>,
     // TODO: consider merging `current_doc_comment` and `current_fn_name`
     pub(crate) current_doc_comment: Option<String>,
-    pub(crate) current_fn_name: Option<String>,
+    pub(crate) current_fn_name: Option<Rc<str>>,
     /// Tracks the current stack of nested `import`-calls to emit an error on cyclic imports.
     /// Note that this only operates on user-written code and does not catch cyclic import
     /// errors within the STL (those still cause a rust stack overflow).
     pub(crate) import_stack: Vec<PathBuf>,
+    /// The modules imported with `import` and the span of the call that imported them first,
+    /// to warn about other calls importing them again.
+    pub(crate) imported_modules: HashMap<String, Span>,
+    /// The warnings about the program.
+    /// This is shared with the states created by `import` and `eval`.
+    diagnostics: Rc<RefCell<Vec<Diagnostic>>>,
     code: Option<String>,
+    /// How many chunks were evaluated by [`eval_more`](Self::eval_more).
+    chunk_count: usize,
+    /// Whether the prelude was already imported into the storage.
+    pub(crate) prelude_imported: bool,
     next_type_id: i64,
     pub(crate) optimizations_enabled: bool,
+    /// The maximum number of evaluation steps, if limited.
+    fuel: Option<u64>,
+    /// The number of evaluation steps done so far.
+    /// This is shared with the states created by `import` and `eval`.
+    steps: Rc<Cell<u64>>,
+    /// The maximum nesting depth of evaluations, see [`with_max_depth`](Self::with_max_depth).
+    max_depth: usize,
+    /// The nesting depth at which this state started, if it was created by another state.
+    depth_base: usize,
+    /// When this flag is set, the program is stopped, see [`with_interrupt`](Self::with_interrupt).
+    interrupt: Option<Arc<AtomicBool>>,
+    /// When this is reached, the program is stopped, see [`with_deadline`](Self::with_deadline).
+    deadline: Option<Instant>,
+    /// The operations the program may perform, see [`with_capabilities`](Self::with_capabilities).
+    pub(crate) capabilities: Capabilities,
+    /// Finds the modules for `import`, see [`with_import_resolver`](Self::with_import_resolver).
+    pub(crate) import_resolver: Rc<dyn ImportResolver>,
+    /// Modules implemented in Rust, see [`add_native_module`](Self::add_native_module).
+    pub(crate) native_modules: Rc<HashMap<String, Vec<(String, Function)>>>,
+    /// Whether this state runs a module of the STL.
+    pub(crate) is_stl_module: bool,
+    /// The environment of the module run by this state, which is set once it was imported.
+    pub(crate) module_env: Rc<ModuleEnv>,
+    /// Keeps the environments of all imported modules alive,
+    /// since functions only hold weak references to them.
+    /// This is shared with the states created by `import` and `eval`.
+    retained_envs: Rc<RefCell<Vec<Rc<ModuleEnv>>>>,
+    /// All modules that were imported so far, by their path.
+    /// This is shared with the states created by `import` and `eval`.
+    module_cache: Rc<RefCell<HashMap<PathBuf, Rc<LoadedModule>>>>,
     // make sure this type can never be constructed from outside
     __private: (),
 }
@@ -59,6 +128,25 @@ impl Default for State {
     reason = "type cannot be constructed in const anyway"
 )]
 impl State {
+    /// The default for [`with_max_depth`](Self::with_max_depth).
+    ///
+    /// It is chosen so that even unoptimized builds stay well below the 8 MiB stack
+    /// that the main thread usually has.
+    pub const DEFAULT_MAX_DEPTH: usize = 1000;
+
+    /// How many of the outermost frames the backtrace of a `RecursionError` keeps.
+    const RECURSION_OUTER_FRAMES: usize = 5;
+    /// How many of the innermost frames the backtrace of a `RecursionError` keeps.
+    const RECURSION_INNER_FRAMES: usize = 20;
+
+    /// How much deeper a nested program run by `import` or `eval` starts,
+    /// since running it uses a lot more of the native stack than a single evaluation.
+    const CHILD_DEPTH_COST: usize = 4;
+
+    /// After how many evaluation steps the deadline is checked again,
+    /// since looking at the clock is comparatively slow.
+    const DEADLINE_CHECK_INTERVAL: u64 = 256;
+
     /// Creates a new state for running a program.
     ///
     /// You must use a method such as [`with_code`](Self::with_code) or [`with_source_file`](Self::with_source_file)
@@ -72,13 +160,33 @@ impl State {
             file_directory: Directory::InternedSTL,
             current_file_path: None,
             exit_unwind_value: None,
+            signal: None,
+            frame: Frame::default(),
+            next_frame_id: 0,
             backtrace: Vec::new(),
             current_doc_comment: None,
             current_fn_name: None,
             import_stack: Vec::new(),
+            imported_modules: HashMap::new(),
+            diagnostics: Rc::default(),
             code: None,
+            chunk_count: 0,
+            prelude_imported: false,
             next_type_id: Atom::MIN_OBJECT_TY_ID,
             optimizations_enabled: false,
+            fuel: None,
+            steps: Rc::new(Cell::new(0)),
+            max_depth: Self::DEFAULT_MAX_DEPTH,
+            depth_base: 0,
+            interrupt: None,
+            deadline: None,
+            capabilities: Capabilities::all(),
+            import_resolver: Rc::new(FsResolver),
+            native_modules: Rc::default(),
+            is_stl_module: false,
+            module_env: Rc::default(),
+            retained_envs: Rc::default(),
+            module_cache: Rc::default(),
             __private: (),
         }
     }
@@ -134,6 +242,228 @@ impl State {
         self
     }
 
+    /// Limits the number of evaluation steps the program may take.
+    ///
+    /// Every evaluation of an argument takes one step, including those done while importing
+    /// modules.
+    /// Once all steps are used up, a `ResourceError` is raised.
+    /// Catching it is possible, but pointless, since every further evaluation raises it again.
+    #[must_use = "this returns the new state without modifying the original"]
+    pub fn with_fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    /// Sets the maximum nesting depth of evaluations, which defaults to
+    /// [`DEFAULT_MAX_DEPTH`](Self::DEFAULT_MAX_DEPTH).
+    ///
+    /// Each nested argument, such as a function call inside of another one, adds one level.
+    /// This means that a call of a recursive function usually takes several levels,
+    /// for example one for the call itself, one for its body and one for each call that
+    /// the recursive call is nested in.
+    /// Going deeper raises a `RecursionError` instead of overflowing the native stack,
+    /// which would abort the whole process.
+    ///
+    /// Only increase this if the program runs on a thread with a larger stack.
+    #[must_use = "this returns the new state without modifying the original"]
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Stops the program once the given flag is set to `true`, for example by another thread.
+    ///
+    /// The program then unwinds just like after a call to `exit`, with an `InterruptedError`
+    /// as its result.
+    /// The flag is checked before every evaluation step, but not while a builtin function
+    /// is blocked, such as when reading input.
+    #[must_use = "this returns the new state without modifying the original"]
+    pub fn with_interrupt(mut self, interrupt: Arc<AtomicBool>) -> Self {
+        self.interrupt = Some(interrupt);
+        self
+    }
+
+    /// Stops the program once the given point in time is reached.
+    ///
+    /// The program then unwinds just like after a call to `exit`, with a `TimeoutError`
+    /// as its result.
+    /// The clock is only checked periodically, so the program may run slightly longer.
+    #[must_use = "this returns the new state without modifying the original"]
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Restricts the operations the program may perform, such as reading input or importing files.
+    ///
+    /// This also applies to imported modules and code run with `eval`.
+    #[must_use = "this returns the new state without modifying the original"]
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// Replaces how `import` finds modules outside of the STL.
+    ///
+    /// By default, this is [`FsResolver`], which looks in the directory of the program.
+    /// This also applies to imported modules and code run with `eval`.
+    #[must_use = "this returns the new state without modifying the original"]
+    pub fn with_import_resolver(mut self, resolver: impl ImportResolver + 'static) -> Self {
+        self.import_resolver = Rc::new(resolver);
+        self
+    }
+
+    /// Raises a `PermissionError` if the capability needed for the given action is missing.
+    pub(crate) fn require(&self, allowed: bool, action: &str) -> Result<()> {
+        if !allowed {
+            raise!(
+                self,
+                PermissionError,
+                "the program is not allowed to {action}"
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns the number of evaluation steps taken so far, see [`with_fuel`](Self::with_fuel).
+    pub fn fuel_consumed(&self) -> u64 {
+        self.steps.get()
+    }
+
+    /// Creates a new state for running another program on behalf of this one,
+    /// such as an imported module.
+    ///
+    /// The new state shares the limits of this state.
+    pub(crate) fn child(&self) -> Self {
+        let mut state = Self::new();
+        state.fuel = self.fuel;
+        state.steps = Rc::clone(&self.steps);
+        state.diagnostics = Rc::clone(&self.diagnostics);
+        state.max_depth = self.max_depth;
+        state.depth_base = self.depth() + Self::CHILD_DEPTH_COST;
+        state.interrupt.clone_from(&self.interrupt);
+        state.deadline = self.deadline;
+        state.capabilities.clone_from(&self.capabilities);
+        state.import_resolver = Rc::clone(&self.import_resolver);
+        state.native_modules = Rc::clone(&self.native_modules);
+        state.retained_envs = Rc::clone(&self.retained_envs);
+        state.module_cache = Rc::clone(&self.module_cache);
+        state
+    }
+
+    /// Makes the top-level values of this state the environment of its module.
+    /// This should be called once the module has finished running.
+    ///
+    /// The module is then stored in the module cache, so that it is not run again when
+    /// it is imported again.
+    pub(crate) fn finish_module(self, result: Atom) -> Rc<LoadedModule> {
+        // a module may only be run once, so the environment cannot be set already
+        let _ = self.module_env.set(self.storage.module_env());
+        self.retained_envs
+            .borrow_mut()
+            .push(Rc::clone(&self.module_env));
+
+        let module = Rc::new(self.storage.into_loaded_module(result));
+        if let Some(path) = self.current_file_path {
+            self.module_cache
+                .borrow_mut()
+                .insert(path, Rc::clone(&module));
+        }
+        module
+    }
+
+    /// Returns the module with the given path if it was already imported before.
+    pub(crate) fn cached_module(&self, path: &Path) -> Option<Rc<LoadedModule>> {
+        self.module_cache.borrow().get(path).cloned()
+    }
+
+    /// Returns the module environment that newly defined functions should use.
+    pub(crate) fn current_module_env(&self) -> Option<Rc<ModuleEnv>> {
+        match self.storage.envs.last() {
+            Some((_, env)) => env.clone(),
+            None => Some(Rc::clone(&self.module_env)),
+        }
+    }
+
+    /// Checks whether the program was interrupted or exceeded its deadline.
+    /// If so, starts unwinding like `exit` and returns `true`.
+    pub(crate) fn poll_interrupt(&mut self) -> bool {
+        let (kind, msg) = if self
+            .interrupt
+            .as_ref()
+            .is_some_and(|flag| flag.load(Ordering::Relaxed))
+        {
+            (InterruptedError, "the program was interrupted")
+        } else if let Some(deadline) = self.deadline
+            && self
+                .steps
+                .get()
+                .is_multiple_of(Self::DEADLINE_CHECK_INTERVAL)
+            && Instant::now() >= deadline
+        {
+            (TimeoutError, "the program exceeded its deadline")
+        } else {
+            return false;
+        };
+        self.exit_unwind_value = Some(Err(Exception::with_trace(kind, msg, &self.backtrace)));
+        true
+    }
+
+    /// The current nesting depth of evaluations, including those of the parent states.
+    fn depth(&self) -> usize {
+        self.depth_base + self.backtrace.len()
+    }
+
+    /// Raises an exception if the maximum nesting depth was exceeded.
+    pub(crate) fn check_depth(&self) -> Result<()> {
+        if self.depth() <= self.max_depth {
+            return Ok(());
+        }
+        Err(self.recursion_error())
+    }
+
+    /// Creates the exception for exceeding the maximum nesting depth,
+    /// keeping only the outermost and innermost frames in its backtrace.
+    ///
+    /// This is kept out of [`check_depth`](Self::check_depth), so that the stack frames of
+    /// evaluations stay small.
+    #[cold]
+    #[inline(never)]
+    fn recursion_error(&self) -> Exception {
+        let mut exc = self.raise(
+            RecursionError,
+            format!("maximum nesting depth of {} exceeded", self.max_depth),
+        );
+        // the first frame is the hidden program wrapper, which is kept in addition
+        let kept_outer = 1 + Self::RECURSION_OUTER_FRAMES;
+        let backtrace_len = exc.backtrace.len();
+        if backtrace_len > kept_outer + Self::RECURSION_INNER_FRAMES {
+            let count = backtrace_len - kept_outer - Self::RECURSION_INNER_FRAMES;
+            exc.backtrace.drain(kept_outer..kept_outer + count);
+            exc.omitted_frames = Some(Box::new(OmittedFrames {
+                index: kept_outer,
+                count,
+            }));
+        }
+        exc
+    }
+
+    /// Counts one evaluation step and raises an exception if the fuel is used up.
+    pub(crate) fn consume_fuel(&self) -> Result<()> {
+        let steps = self.steps.get() + 1;
+        self.steps.set(steps);
+        if let Some(fuel) = self.fuel
+            && steps > fuel
+        {
+            raise!(
+                self,
+                ResourceError,
+                "fuel exhausted: the program took more than {fuel} evaluation steps"
+            );
+        }
+        Ok(())
+    }
+
     /// Sets the current directory to the operating systems current working directory.
     ///
     /// Note that this does not set or change the program code.
@@ -152,14 +482,10 @@ impl State {
     /// # Panics
     /// Panics if `code` was not set.
     pub fn run(&mut self) -> Result<Atom> {
-        // newlines are needed to avoid interaction with comments
-        // and also help with calculating the actual spans (just do line - 1)
-        let code = format!(
-            "_(__builtin_prelude_import(),\n{}\n)",
-            self.code
-                .as_ref()
-                .expect("setting the source code is required")
-        );
+        let code = self
+            .code
+            .clone()
+            .expect("setting the source code is required");
 
         let file_path = if let Some(path) = &self.current_file_path {
             Rc::new(path.clone())
@@ -167,20 +493,174 @@ impl State {
             no_path()
         };
 
-        let tokens = tokenize(&code, file_path)?;
+        self.run_code(&code, file_path)
+    }
+
+    /// Parses and evaluates another chunk of code on this state.
+    ///
+    /// Unlike [`run`](Self::run), this does not need any code to be set beforehand
+    /// and it keeps everything that earlier runs defined, so it can be called repeatedly
+    /// to drive a long-lived session.
+    /// The prelude is only imported by the first run on a state.
+    ///
+    /// The spans of each chunk point into a pseudo-file called `<chunk:N>`, where `N` counts
+    /// the chunks evaluated so far, starting at 1.
+    ///
+    /// Once the program was stopped by `exit`, further chunks are not evaluated and the exit
+    /// value is returned again.
+    pub fn eval_more(&mut self, code: impl AsRef<str>) -> Result<Atom> {
+        self.chunk_count += 1;
+        let file_path = Rc::new(chunk_path(self.chunk_count));
+        self.run_code(code.as_ref(), file_path)
+    }
+
+    /// Returns the pseudo-file the spans of the last chunk evaluated by
+    /// [`eval_more`](Self::eval_more) point into, or `None` if none was evaluated yet.
+    ///
+    /// Hosts can pass it to [`ErrorRenderer::add_source`] together with the chunk,
+    /// so that exceptions raised by the chunk show its code.
+    pub fn last_chunk_path(&self) -> Option<PathBuf> {
+        (self.chunk_count > 0).then(|| chunk_path(self.chunk_count))
+    }
+
+    fn run_code(&mut self, code: &str, file_path: Rc<PathBuf>) -> Result<Atom> {
+        // the STL never changes, so it only has to be parsed once per thread
+        let program = if self.is_stl_module {
+            let key = ((*file_path).clone(), self.optimizations_enabled);
+            if let Some(program) = PARSED_STL.with_borrow(|parsed| parsed.get(&key).cloned()) {
+                program
+            } else {
+                let program = Rc::new(self.parse(code, file_path)?);
+                PARSED_STL.with_borrow_mut(|parsed| parsed.insert(key, Rc::clone(&program)));
+                program
+            }
+        } else {
+            Rc::new(self.parse(code, file_path)?)
+        };
+
+        let result = program.eval(self).map(Cow::into_owned);
+
+        // errors caused by unwinding (such as a `while` condition evaluating to `null`)
+        // are not relevant
+        if let Some(exit_unwind_value) = &self.exit_unwind_value {
+            return exit_unwind_value.clone();
+        }
+        self.check_stray_signal()?;
+
+        result
+    }
+
+    fn parse(&self, code: &str, file_path: Rc<PathBuf>) -> Result<Argument> {
+        let tokens = tokenize(code, Rc::clone(&file_path))?;
 
-        let mut program = build_program(tokens)?;
+        let mut program = build_program(tokens, file_path)?;
+        self.check_parsed(&program);
         if self.optimizations_enabled {
             run_optimizations(&mut program);
         }
+        Ok(program)
+    }
+
+    /// Makes a Rust closure available to the program as a function with the given name.
+    ///
+    /// The closure's parameters are evaluated and converted automatically,
+    /// see [`Function::from_native`] for details.
+    ///
+    /// # Example
+    /// ```rust
+    /// use regulus::prelude::*;
+    /// let mut state = State::new();
+    /// state.register_fn("repeat", "Repeats a string.", |s: String, n: usize| {
+    ///     Ok(s.repeat(n))
+    /// });
+    /// let result = state.with_code(r#"repeat("ab", 2)"#).run().unwrap();
+    /// assert_eq!(result.as_string().unwrap(), "abab");
+    /// ```
+    pub fn register_fn<Args, Ret>(
+        &mut self,
+        name: impl AsRef<str>,
+        doc: impl Into<String>,
+        f: impl NativeFunction<Args, Ret>,
+    ) {
+        self.storage
+            .insert(name, Atom::Function(Function::from_native(doc, f)));
+    }
+
+    /// Adds a module implemented in Rust, whose functions are only defined once the program
+    /// imports it with `import(name)`.
+    ///
+    /// Native modules take precedence over modules found by the
+    /// [import resolver](Self::with_import_resolver) and the STL.
+    /// They can also be imported by imported modules.
+    ///
+    /// # Example
+    /// ```rust
+    /// use regulus::prelude::*;
+    /// mod greetings {
+    ///     use regulus::prelude::*;
+    ///     functions! {
+    ///         /// Greets the given name.
+    ///         "greet"(1) => |state, args| {
+    ///             let name = args[0].eval_as_string(state)?;
+    ///             Ok(Atom::new_string(&format!("hello, {name}")))
+    ///         }
+    ///     }
+    /// }
+    /// let mut state = State::new();
+    /// state.add_native_module("greetings", greetings::functions());
+    /// let result = state.with_code(r#"import(greetings), greet("you")"#).run().unwrap();
+    /// assert_eq!(result.as_string().unwrap(), "hello, you");
+    /// ```
+    pub fn add_native_module(&mut self, name: impl Into<String>, functions: Vec<(&str, Function)>) {
+        let functions = functions
+            .into_iter()
+            .map(|(name, f)| (name.to_string(), f))
+            .collect();
+        Rc::make_mut(&mut self.native_modules).insert(name.into(), functions);
+    }
 
-        let result = program.eval(self)?.into_owned();
+    /// Calls the function with the given name, for example one that the program defined with `def`.
+    ///
+    /// The given values are passed as the arguments, see [`Function::call_with_values`].
+    /// This is usually done after [`run`](Self::run), so that scripts can provide callbacks for
+    /// the host.
+    ///
+    /// If the function stops the program with `exit`, the exit value is returned.
+    pub fn call(&mut self, name: impl AsRef<str>, values: Vec<Atom>) -> Result<Atom> {
+        let name = name.as_ref();
+        // the outermost backtrace entry is never shown, like the implicit program wrapper
+        self.backtrace.push(host_span().into());
+        let func = match self.storage.get(name).as_deref() {
+            Some(Atom::Function(func)) => func.clone(),
+            Some(_) => {
+                let exc = self.raise(NameError, format!("`{name}` is not a function"));
+                self.backtrace.pop();
+                return Err(exc);
+            }
+            None => {
+                let exc = self.raise_unknown_name(format!("no function `{name}` found"), name);
+                self.backtrace.pop();
+                return Err(exc);
+            }
+        };
+        self.current_fn_name = Some(name.into());
+        let result = func.call_with_values(self, values);
 
         if let Some(exit_unwind_value) = &self.exit_unwind_value {
+            self.backtrace.pop();
             return exit_unwind_value.clone();
         }
+        let stray_signal = self.check_stray_signal();
+        self.backtrace.pop();
+        stray_signal?;
+        result
+    }
 
-        Ok(result)
+    /// Returns whether the program was stopped by a call to `exit`.
+    ///
+    /// Once this is the case, running further code on this state has no effect.
+    pub fn has_exited(&self) -> bool {
+        self.exit_unwind_value.is_some()
     }
 
     /// Writes the given string to stdout, without any extra newline.
@@ -188,7 +668,7 @@ impl State {
         self.stdout.as_write().write_all(msg.as_bytes()).unwrap();
     }
 
-    /// Writes the given string to stdout, without any extra newline.
+    /// Writes the given string to stderr, without any extra newline.
     pub(crate) fn write_to_stderr(&mut self, msg: &str) {
         self.stderr.as_write().write_all(msg.as_bytes()).unwrap();
     }
@@ -205,12 +685,31 @@ impl State {
         old
     }
 
+    /// Creates a new type of opaque host values with the given name and methods.
+    ///
+    /// Values of this type are created with [`NativeType::wrap`] and get a new type id.
+    /// The methods can be called by the program with `@`, which passes the value itself as the
+    /// first argument.
+    /// Unless a `==` method is given, values of this type are only equal to themselves.
+    pub fn make_native_type(
+        &mut self,
+        name: impl Into<String>,
+        methods: Vec<(&str, Function)>,
+    ) -> NativeType {
+        NativeType::new(name.into(), self.make_type_id(), methods)
+    }
+
     /// Constructs a new exception with the given error and message at the current point of execution.
     pub fn raise(&self, error: impl Into<String>, msg: impl Into<String>) -> Exception {
         Exception::with_trace(error, msg, &self.backtrace)
     }
 }
 
+/// The pseudo-file the spans of the `n`th chunk evaluated by [`State::eval_more`] point into.
+fn chunk_path(n: usize) -> PathBuf {
+    PathBuf::from(format!("<chunk:{n}>"))
+}
+
 /// Helper trait for types that can both be read from and written to.
 pub trait ReadAndWrite: Read + Write {}
 
@@ -280,3 +779,381 @@ impl WriteHandle {
         String::from_utf8(vec).unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn eval_more_keeps_storage() {
+        let mut state = State::new().with_cwd();
+        assert_eq!(state.eval_more("=(x, 2)").unwrap(), Atom::Null);
+        assert_eq!(
+            state.eval_more("def(double, n, *(n, 2))").unwrap(),
+            Atom::Null
+        );
+        assert_eq!(state.eval_more("double(x)").unwrap(), Atom::Int(4));
+        // a redefined prelude function must not be overwritten by a second prelude import
+        state.eval_more("def(print, x, 5)").unwrap();
+        assert_eq!(state.eval_more("print(1)").unwrap(), Atom::Int(5));
+    }
+
+    #[test]
+    fn eval_more_spans() {
+        let mut state = State::new().with_cwd();
+        assert_eq!(state.last_chunk_path(), None);
+        state.eval_more("=(x, 1)").unwrap();
+        let error = state.eval_more("\n=(y, missing)").unwrap_err();
+        assert_eq!(error.backtrace.last().unwrap().to_string(), "<chunk:2>:2:6");
+        assert_eq!(state.last_chunk_path(), Some(PathBuf::from("<chunk:2>")));
+    }
+
+    #[test]
+    fn eval_more_after_exit() {
+        let mut state = State::new().with_cwd();
+        assert_eq!(state.eval_more("exit(3)").unwrap(), Atom::Int(3));
+        assert!(state.has_exited());
+        assert_eq!(state.eval_more("4").unwrap(), Atom::Int(3));
+    }
+
+    #[test]
+    fn registered_functions() {
+        let mut state = State::new().with_cwd();
+        state.register_fn("add", "Adds two ints.", |a: i64, b: i64| {
+            Ok(Atom::Int(a + b))
+        });
+        state.register_fn("answer", "", || Ok(Atom::Int(42)));
+        state.register_fn("fail", "", |msg: String| -> Result<()> {
+            Err(Exception::new("Host", msg))
+        });
+
+        assert_eq!(state.eval_more("add(answer(), 1)").unwrap(), Atom::Int(43));
+        assert_eq!(
+            state.eval_more("add(1, 2, 3)").unwrap_err().to_string(),
+            "ArgumentError: expected `2` args, found `3` args for `add`\nat add (<chunk:2>:1:4)"
+        );
+        assert_eq!(
+            state.eval_more("add(1, true)").unwrap_err().to_string(),
+            "TypeError: true is not a Int\nat add (<chunk:3>:1:4)"
+        );
+        assert_eq!(
+            state.eval_more("fail(\"oops\")").unwrap_err().to_string(),
+            "HostError: oops\nat fail (<chunk:4>:1:5)"
+        );
+    }
+
+    #[test]
+    fn calling_script_functions() {
+        let mut state = State::new().with_cwd().with_code(
+            "
+            =(events, list()),
+            def(on_event, name, count, _(
+                =(events, append(events, name)),
+                *(count, 2)
+            )),
+            def(stop, exit(\"stopped\")),
+            ",
+        );
+        state.run().unwrap();
+
+        assert_eq!(
+            state
+                .call("on_event", vec![Atom::new_string("a"), Atom::Int(2)])
+                .unwrap(),
+            Atom::Int(4)
+        );
+        let exc = state
+            .call("on_event", vec![Atom::Null, Atom::Null])
+            .unwrap_err();
+        assert_eq!(exc.error, "Arithmetic");
+        assert_eq!(exc.backtrace[0].to_string(), "<host>:1:1");
+        assert_eq!(
+            state.call("events", vec![]).unwrap_err().to_string(),
+            "NameError: `events` is not a function\nat <host>:1:1"
+        );
+        assert_eq!(
+            state.call("stop", vec![]).unwrap().as_string().unwrap(),
+            "stopped"
+        );
+    }
+
+    #[test]
+    fn fuel_limit() {
+        let mut state = State::new().with_cwd().with_fuel(100_000);
+        let exc = state.eval_more("while(true, null)").unwrap_err();
+        assert_eq!(
+            exc.to_string(),
+            "ResourceError: fuel exhausted: the program took more than 100000 evaluation steps\nat <chunk:1>:1:13\nat while (<chunk:1>:1:6)"
+        );
+        assert_eq!(state.fuel_consumed(), 100_001);
+        assert!(state.eval_more("run_or_string_exception(1)").is_err());
+    }
+
+    #[test]
+    fn fuel_is_shared_with_imports() {
+        let mut state = State::new().with_cwd();
+        state.eval_more("null").unwrap();
+        let prelude_steps = state.fuel_consumed();
+        assert!(prelude_steps > 0);
+        state.eval_more("import(sorting)").unwrap();
+        assert!(state.fuel_consumed() > prelude_steps + 1);
+    }
+
+    #[test]
+    fn recursion_limit() {
+        // run on a thread with the usual stack size of the main thread
+        thread::Builder::new()
+            .stack_size(8 * 1024 * 1024)
+            .spawn(|| {
+                let mut state = State::new().with_cwd();
+                state
+                    .eval_more("def(f, n, ifelse(==(n, 0), 0, +(1, f(-(n, 1)))))")
+                    .unwrap();
+                assert_eq!(state.eval_more("f(10)").unwrap(), Atom::Int(10));
+                let exc = state.eval_more("f(100000)").unwrap_err();
+                assert_eq!(exc.error, "Recursion");
+                assert_eq!(exc.backtrace.len(), 26);
+                let shown = exc.to_string();
+                // the call that started the recursion is still shown
+                assert!(shown.ends_with("at f (<chunk:3>:1:2)"), "{shown}");
+                assert!(shown.contains("frames omitted\n"));
+                // the state can still be used afterwards
+                assert_eq!(state.eval_more("f(5)").unwrap(), Atom::Int(5));
+
+                let mut state = State::new().with_cwd().with_max_depth(30);
+                state.eval_more("def(g, n, g(n))").unwrap();
+                assert_eq!(
+                    state.eval_more("g(1)").unwrap_err().msg,
+                    "maximum nesting depth of 30 exceeded"
+                );
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn interrupts() {
+        let flag = Arc::new(AtomicBool::new(false));
+        let mut state = State::new().with_cwd().with_interrupt(Arc::clone(&flag));
+        state.eval_more("=(x, 1)").unwrap();
+        let setter = {
+            let flag = Arc::clone(&flag);
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                flag.store(true, Ordering::Relaxed);
+            })
+        };
+        let exc = state.eval_more("while(true, =(x, +(x, 1)))").unwrap_err();
+        setter.join().unwrap();
+        assert_eq!(exc.error, "Interrupted");
+        assert_eq!(exc.msg, "the program was interrupted");
+        assert!(state.has_exited());
+
+        // imported code is interrupted too, while it is running
+        let flag = Arc::new(AtomicBool::new(false));
+        let mut state = State::new()
+            .with_interrupt(Arc::clone(&flag))
+            .with_import_resolver(MemoryResolver::from([("spin", "while(true, _())")]))
+            .with_code("import(spin), print(1)");
+        let setter = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            flag.store(true, Ordering::Relaxed);
+        });
+        let exc = state.run().unwrap_err();
+        setter.join().unwrap();
+        assert_eq!(exc.error, "Interrupted");
+        assert!(
+            exc.backtrace
+                .iter()
+                .any(|frame| frame.span.file.to_str() == Some("<module:spin>")),
+            "{exc}"
+        );
+    }
+
+    #[test]
+    fn deadlines() {
+        let mut state = State::new()
+            .with_cwd()
+            .with_deadline(Instant::now() + Duration::from_millis(50))
+            .with_code("while(true, _())");
+        let exc = state.run().unwrap_err();
+        assert_eq!(exc.error, "Timeout");
+        assert_eq!(exc.msg, "the program exceeded its deadline");
+    }
+
+    #[test]
+    fn stray_signals() {
+        let mut state = State::new().with_cwd();
+        // the lazy argument refers to `leak`, which already returned when it is evaluated
+        state
+            .eval_more("def(keep, $a, a), def(leak, keep(return(1))), def(call, g, g())")
+            .unwrap();
+        assert_eq!(
+            state.eval_more("call(leak())").unwrap_err().msg,
+            "`return` was used after its function or loop already ended"
+        );
+        // the state keeps working afterwards
+        assert_eq!(state.eval_more("+(1, 2)").unwrap(), Atom::Int(3));
+
+        state.eval_more("def(f, x, return(x))").unwrap();
+        assert_eq!(state.call("f", vec![Atom::Int(4)]).unwrap(), Atom::Int(4));
+    }
+
+    #[test]
+    fn denied_capabilities() {
+        let sandboxed = || {
+            State::new()
+                .with_cwd()
+                .with_capabilities(Capabilities::none())
+        };
+        let error = |code: &str| sandboxed().with_code(code).run().unwrap_err();
+
+        assert_eq!(
+            error("write(1)").msg,
+            "the program is not allowed to write to stdout"
+        );
+        assert_eq!(
+            error("write_err(1)").msg,
+            "the program is not allowed to write to stderr"
+        );
+        assert_eq!(error("input()").error, "Permission");
+        assert_eq!(error("eval(\"1\")").error, "Permission");
+        assert_eq!(error("import(time), now()").error, "Permission");
+
+        // the RNG falls back to a fixed seed
+        let rand = || {
+            sandboxed()
+                .with_code("import(random), rand()")
+                .run()
+                .unwrap()
+        };
+        assert_eq!(rand(), rand());
+    }
+
+    #[test]
+    fn import_directories() {
+        let dir = env::temp_dir().join(format!("regulus_imports_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("module_a.re"), "=(a, 1)").unwrap();
+        fs::write(dir.join("main.re"), "import(module_a), a").unwrap();
+
+        let run_with = |fs_import| {
+            State::new()
+                .with_source_file(dir.join("main.re"))
+                .unwrap()
+                .with_capabilities(Capabilities {
+                    fs_import,
+                    ..Capabilities::all()
+                })
+                .run()
+        };
+        assert_eq!(run_with(FsImport::Any).unwrap(), Atom::Int(1));
+        assert_eq!(
+            run_with(FsImport::Only(vec![dir.clone()])).unwrap(),
+            Atom::Int(1)
+        );
+        assert_eq!(
+            run_with(FsImport::Only(vec![dir.join("other")]))
+                .unwrap_err()
+                .error,
+            "Permission"
+        );
+        assert_eq!(run_with(FsImport::Denied).unwrap_err().error, "Permission");
+
+        // the STL is always available
+        fs::write(dir.join("main.re"), "import(range), len(range(0, 3))").unwrap();
+        assert_eq!(run_with(FsImport::Denied).unwrap(), Atom::Int(3));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn native_modules() {
+        let mut state = State::new()
+            .with_cwd()
+            .with_import_resolver(MemoryResolver::from([(
+                "wrapper",
+                "import(math_ext), def(quadruple, x, double(double(x)))",
+            )]));
+        state.add_native_module(
+            "math_ext",
+            vec![(
+                "double",
+                Function::from_native("Doubles an int.", |x: i64| Ok(x * 2)),
+            )],
+        );
+
+        assert_eq!(state.eval_more("double(1)").unwrap_err().error, "Name");
+        assert_eq!(
+            state.eval_more("import(wrapper), quadruple(2)").unwrap(),
+            Atom::Int(8)
+        );
+        assert_eq!(
+            state.eval_more("import(math_ext), double(3)").unwrap(),
+            Atom::Int(6)
+        );
+    }
+
+    #[test]
+    fn modules_run_once() {
+        let runs = Rc::new(Cell::new(0));
+        let mut state = State::new()
+            .with_cwd()
+            .with_import_resolver(MemoryResolver::from([
+                ("counted", "import(host), count_run(), =(value, 1)"),
+                ("importer", "import(counted)"),
+            ]));
+        let counter = Rc::clone(&runs);
+        state.add_native_module(
+            "host",
+            vec![(
+                "count_run",
+                Function::from_native("", move || {
+                    counter.set(counter.get() + 1);
+                    Ok(())
+                }),
+            )],
+        );
+
+        state
+            .eval_more("import(counted), =(value, 2), import(importer), import(counted)")
+            .unwrap();
+        assert_eq!(runs.get(), 1);
+        // importing again still adds the values again
+        assert_eq!(state.eval_more("value").unwrap(), Atom::Int(1));
+    }
+
+    #[test]
+    fn module_envs_share_builtins() {
+        let mut state = State::new()
+            .with_cwd()
+            .with_import_resolver(MemoryResolver::from([
+                ("choosing", "def(choose, ifelse(true, 1, 2))"),
+                (
+                    "redefining",
+                    "def(ifelse, a, b, c, 0), def(pick, ifelse(true, 1, 2))",
+                ),
+                ("both", "import(choosing), import(redefining)"),
+            ]));
+        state
+            .eval_more("import(both), import(choosing), def(ifelse, a, b, c, 3)")
+            .unwrap();
+        // a module uses the original builtin unless it redefined it itself
+        assert_eq!(state.eval_more("choose()").unwrap(), Atom::Int(1));
+        assert_eq!(state.eval_more("pick()").unwrap(), Atom::Int(0));
+
+        // the environments only contain what the modules defined or imported themselves
+        let envs = state.retained_envs.borrow();
+        for env in envs.iter() {
+            let env = env.get().unwrap();
+            assert!(!env.contains_key("write"), "{:?}", env.keys());
+        }
+        assert!(envs.iter().any(|env| {
+            let env = env.get().unwrap();
+            env.contains_key("pick") && env.contains_key("ifelse")
+        }));
+    }
+}
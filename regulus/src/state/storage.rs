@@ -1,20 +1,102 @@
 use crate::Atom;
-use crate::builtins::all_functions;
-use std::collections::HashMap;
+use crate::argument::Argument;
+use crate::builtins::builtins;
+use std::borrow::Cow;
+use std::cell::{OnceCell, RefCell};
 use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+/// A local value that was captured by a function, which shares it with the function call
+/// that defined it.
+/// Assignments by either of them are seen by the other one.
+///
+/// The value is `None` as long as the name is not bound yet,
+/// which happens when a function uses a name that is only assigned after the function was defined.
+pub(crate) type SharedValue = Rc<RefCell<Option<Atom>>>;
+
+pub(crate) enum Local {
+    Owned(Atom),
+    Shared(SharedValue),
+}
+
+impl Local {
+    /// Returns the value or `None` if it is a shared value that is not bound yet.
+    fn as_atom(&self) -> Option<Cow<'_, Atom>> {
+        match self {
+            Self::Owned(atom) => Some(Cow::Borrowed(atom)),
+            Self::Shared(shared) => shared.borrow().clone().map(Cow::Owned),
+        }
+    }
+
+    fn into_atom(self) -> Option<Atom> {
+        match self {
+            Self::Owned(atom) => Some(atom),
+            Self::Shared(shared) => shared.borrow().clone(),
+        }
+    }
+
+    fn is_bound(&self) -> bool {
+        match self {
+            Self::Owned(_) => true,
+            Self::Shared(shared) => shared.borrow().is_some(),
+        }
+    }
+
+    fn set(&mut self, atom: Atom) {
+        match self {
+            Self::Owned(owned) => *owned = atom,
+            Self::Shared(shared) => *shared.borrow_mut() = Some(atom),
+        }
+    }
+
+    /// Turns the value into a shared one if it is not yet and returns it.
+    fn share(&mut self) -> SharedValue {
+        if let Self::Owned(atom) = self {
+            *self = Self::Shared(Rc::new(RefCell::new(Some(std::mem::replace(
+                atom,
+                Atom::Null,
+            )))));
+        }
+        let Self::Shared(shared) = self else {
+            unreachable!()
+        };
+        Rc::clone(shared)
+    }
+}
 
 pub(crate) enum StoredValue {
     Global(Atom),
     /// An identifier may refer to any number of atoms within different scopes.
     /// Only the innermost one will be considered, until its scope ends.
-    Locals(Vec<(usize, Atom)>),
+    /// Shared values that are not bound yet are skipped.
+    Locals(Vec<(usize, Local)>),
 }
 
 impl StoredValue {
-    pub fn as_atom(&self) -> Option<&Atom> {
+    pub fn as_atom(&self) -> Option<Cow<'_, Atom>> {
+        match self {
+            Self::Global(a) => Some(Cow::Borrowed(a)),
+            Self::Locals(v) => v.iter().rev().find_map(|(_, local)| local.as_atom()),
+        }
+    }
+
+    /// Returns whether this is a global or a local that is bound in a scope after the given one.
+    fn is_bound_after(&self, scope: usize) -> bool {
         match self {
-            Self::Global(a) => Some(a),
-            Self::Locals(v) => v.last().map(|(_, a)| a),
+            Self::Global(_) => true,
+            Self::Locals(v) => v
+                .iter()
+                .rev()
+                .find(|(_, local)| local.is_bound())
+                .is_some_and(|(bound_scope, _)| *bound_scope > scope),
+        }
+    }
+
+    fn is_bound(&self) -> bool {
+        match self {
+            Self::Global(_) => true,
+            Self::Locals(v) => v.iter().any(|(_, local)| local.is_bound()),
         }
     }
 
@@ -25,9 +107,9 @@ impl StoredValue {
                 if let Some(last) = vec.last_mut()
                     && last.0 == scope
                 {
-                    last.1 = atom;
+                    last.1.set(atom);
                 } else {
-                    vec.push((scope, atom));
+                    vec.push((scope, Local::Owned(atom)));
                 }
             }
         }
@@ -43,10 +125,43 @@ impl StoredValue {
     }
 }
 
+/// The top-level values of an imported module, set once the module has finished running.
+///
+/// Functions defined in the module look up names here, so that they keep working when they
+/// are called from a program that does not have those names itself.
+/// Builtins the module did not redefine are left out, they are looked up in
+/// [`Storage::builtins`] instead.
+pub(crate) type ModuleEnv = OnceCell<HashMap<String, Atom>>;
+
+/// The values of a module that finished running.
+///
+/// This is kept so that importing the same module again does not need to run it again.
+pub(crate) struct LoadedModule {
+    /// All top-level values, including private ones and those imported by the module.
+    pub(crate) values: Vec<(String, Atom)>,
+    /// The public values the module defined itself.
+    pub(crate) exports: HashMap<String, Atom>,
+    pub(crate) globals: Vec<(String, Atom)>,
+    /// The value the module evaluated to.
+    pub(crate) result: Atom,
+}
+
 // TODO: consider merging this type with `State`
 pub struct Storage {
     pub(crate) data: HashMap<String, StoredValue>,
     pub(crate) current_scope: usize,
+    /// The top-level names which were not defined by the program itself,
+    /// but are builtins or were imported.
+    pub(crate) foreign: HashSet<String>,
+    /// The module environments of the functions that are currently being called,
+    /// together with the scope the call started in.
+    /// `None` is used for functions that are not part of a module.
+    pub(crate) envs: Vec<(usize, Option<Rc<ModuleEnv>>)>,
+    /// The original builtins, shared by all storages,
+    /// which module functions use unless their module redefined them.
+    builtins: Rc<HashMap<String, Atom>>,
+    /// The code passed to `defer`, together with the scope it was deferred in.
+    deferred: Vec<(usize, Argument)>,
 }
 
 #[expect(
@@ -55,26 +170,81 @@ pub struct Storage {
 )]
 impl Storage {
     pub fn initial() -> Self {
+        let builtins = builtins();
         Self {
-            data: all_functions()
-                .into_iter()
-                .map(|(name, f)| (name, StoredValue::Locals(vec![(0, f)])))
+            foreign: builtins.keys().cloned().collect(),
+            data: builtins
+                .iter()
+                .map(|(name, f)| {
+                    let locals = vec![(0, Local::Owned(f.clone()))];
+                    (name.clone(), StoredValue::Locals(locals))
+                })
                 .collect(),
             current_scope: 0,
+            envs: Vec::new(),
+            builtins,
+            deferred: Vec::new(),
         }
     }
 
-    pub fn get(&self, name: impl AsRef<str>) -> Option<&Atom> {
-        self.data.get(name.as_ref())?.as_atom()
+    /// Returns the value with the given name.
+    ///
+    /// This is only borrowed from the storage if it is not shared with a function that
+    /// captured it.
+    pub fn get(&self, name: impl AsRef<str>) -> Option<Cow<'_, Atom>> {
+        let name = name.as_ref();
+        let stored = self.data.get(name);
+        // inside of a module function, the names of the module take precedence over everything
+        // except the function's own locals and globals
+        if let Some((call_scope, Some(env))) = self.envs.last() {
+            let is_local = stored.is_some_and(|stored| stored.is_bound_after(*call_scope));
+            if !is_local
+                && let Some(atom) = env
+                    .get()
+                    .and_then(|env| env.get(name))
+                    .or_else(|| self.builtins.get(name))
+            {
+                return Some(Cow::Borrowed(atom));
+            }
+        }
+        stored?.as_atom()
     }
 
     pub fn insert(&mut self, name: impl AsRef<str>, value: Atom) {
+        if self.current_scope == 0 {
+            self.foreign.remove(name.as_ref());
+        }
         match self.data.entry(name.as_ref().to_string()) {
             Entry::Occupied(mut entry) => {
                 entry.get_mut().update(value, self.current_scope);
             }
             Entry::Vacant(entry) => {
-                entry.insert(StoredValue::Locals(vec![(self.current_scope, value)]));
+                entry.insert(StoredValue::Locals(vec![(
+                    self.current_scope,
+                    Local::Owned(value),
+                )]));
+            }
+        }
+    }
+
+    /// Adds a value shared with another function call as a local of the current scope.
+    pub(crate) fn insert_shared(&mut self, name: impl AsRef<str>, value: SharedValue) {
+        let local = (self.current_scope, Local::Shared(value));
+        match self.data.entry(name.as_ref().to_string()) {
+            Entry::Occupied(mut entry) => match entry.get_mut() {
+                StoredValue::Locals(locals) => {
+                    if locals
+                        .last()
+                        .is_some_and(|(scope, _)| *scope == self.current_scope)
+                    {
+                        locals.pop();
+                    }
+                    locals.push(local);
+                }
+                StoredValue::Global(_) => {}
+            },
+            Entry::Vacant(entry) => {
+                entry.insert(StoredValue::Locals(vec![local]));
             }
         }
     }
@@ -88,6 +258,23 @@ impl Storage {
         self.current_scope += 1;
     }
 
+    /// Registers code to run once the current scope ends.
+    pub(crate) fn defer(&mut self, code: Argument) {
+        self.deferred.push((self.current_scope, code));
+    }
+
+    /// Removes the code that was deferred in the current scope,
+    /// in the order it should be run in, which is the reverse of the order it was deferred in.
+    pub(crate) fn take_deferred(&mut self) -> Vec<Argument> {
+        let mut deferred = Vec::new();
+        while let Some((scope, _)) = self.deferred.last()
+            && *scope == self.current_scope
+        {
+            deferred.push(self.deferred.pop().unwrap().1);
+        }
+        deferred
+    }
+
     pub(crate) fn end_scope(&mut self) {
         // TODO: consider removing dead locals (with an empty vec) from the storage
         for val in self.data.values_mut() {
@@ -106,23 +293,170 @@ impl Storage {
                 }
                 StoredValue::Locals(mut locals) => {
                     assert_eq!(locals.len(), 1);
-                    self.insert(name, locals.pop().unwrap().1);
+                    let atom = locals.pop().unwrap().1.into_atom().unwrap();
+                    self.insert_imported(name, atom);
                 }
             }
         }
     }
 
+    /// Adds the values of an imported module, except for private values,
+    /// which are those whose name starts with `_`.
+    ///
+    /// The globals of the module are only added if the module was just run, since they may
+    /// have changed since then otherwise.
+    /// Missing globals are always added.
+    pub(crate) fn add_module(&mut self, module: &LoadedModule, just_run: bool) {
+        self.add_module_globals(module, just_run);
+        for (name, value) in &module.values {
+            if !name.starts_with('_') {
+                self.insert_imported(name, value.clone());
+            }
+        }
+    }
+
+    /// Adds the globals of an imported module, see [`add_module`](Self::add_module).
+    pub(crate) fn add_module_globals(&mut self, module: &LoadedModule, just_run: bool) {
+        for (name, value) in &module.globals {
+            if just_run || !self.data.contains_key(name) {
+                self.add_global(name, value.clone());
+            }
+        }
+    }
+
+    /// Turns the storage of a module that finished running into a [`LoadedModule`].
+    pub(crate) fn into_loaded_module(self, result: Atom) -> LoadedModule {
+        LoadedModule {
+            values: self
+                .top_level_locals()
+                .map(|(name, atom)| (name.to_string(), atom.clone()))
+                .collect(),
+            exports: self
+                .exports()
+                .map(|(name, atom)| (name.to_string(), atom.clone()))
+                .collect(),
+            globals: self.all_globals().collect(),
+            result,
+        }
+    }
+
+    /// Inserts a value that was imported from another module.
+    pub(crate) fn insert_imported(&mut self, name: impl AsRef<str>, value: Atom) {
+        self.insert(&name, value);
+        if self.current_scope == 0 {
+            self.foreign.insert(name.as_ref().to_string());
+        }
+    }
+
+    /// Returns the top-level values that the program defined itself and that are public,
+    /// meaning that their name does not start with `_`.
+    pub(crate) fn exports(&self) -> impl Iterator<Item = (&str, &Atom)> {
+        self.top_level_locals()
+            .filter(|(name, _)| !self.foreign.contains(*name) && !name.starts_with('_'))
+    }
+
+    /// Returns the top-level values which should be part of the module environment,
+    /// which are all except globals and the builtins the module did not redefine.
+    ///
+    /// Those builtins are still found through [`builtins`](Self::builtins),
+    /// so that redefining them in another module does not change the behavior of this module.
+    pub(crate) fn module_env(&self) -> HashMap<String, Atom> {
+        self.top_level_locals()
+            .filter(|(name, atom)| !self.is_original_builtin(name, atom))
+            .map(|(name, atom)| (name.to_string(), atom.clone()))
+            .collect()
+    }
+
+    /// Returns whether the value is the builtin with the given name, not a redefinition of it.
+    pub(crate) fn is_original_builtin(&self, name: &str, value: &Atom) -> bool {
+        match (self.builtins.get(name), value) {
+            (Some(Atom::Function(builtin)), Atom::Function(function)) => builtin.ptr_eq(function),
+            _ => false,
+        }
+    }
+
+    fn top_level_locals(&self) -> impl Iterator<Item = (&str, &Atom)> {
+        self.data.iter().filter_map(|(name, value)| match value {
+            // top-level values are never shared, since only locals of function calls are captured
+            StoredValue::Locals(locals) => match locals.as_slice() {
+                [(0, Local::Owned(atom))] => Some((name.as_str(), atom)),
+                _ => None,
+            },
+            StoredValue::Global(_) => None,
+        })
+    }
+
+    /// Shares the locals with the given names with a function that is being defined
+    /// and returns them.
+    ///
+    /// Names that do not refer to a local of a function call that is currently running yet
+    /// get a shared value in the current scope that is not bound,
+    /// so that the function sees them once they are assigned.
+    /// Locals of the caller of a module function are not shared,
+    /// since the module function cannot see them.
+    /// Globals are never shared.
+    pub(crate) fn capture<'a>(
+        &mut self,
+        names: impl IntoIterator<Item = &'a str>,
+    ) -> HashMap<String, SharedValue> {
+        let min_scope = match self.envs.last() {
+            Some((call_scope, Some(_))) => *call_scope,
+            _ => 0,
+        };
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let stored = self
+                    .data
+                    .entry(name.to_string())
+                    .or_insert_with(|| StoredValue::Locals(Vec::new()));
+                let StoredValue::Locals(locals) = stored else {
+                    return None;
+                };
+                if locals.last().is_none_or(|(scope, _)| *scope <= min_scope) {
+                    let unbound = Local::Shared(Rc::new(RefCell::new(None)));
+                    locals.push((self.current_scope, unbound));
+                }
+                let (_, local) = locals.last_mut().unwrap();
+                Some((name.to_string(), local.share()))
+            })
+            .collect()
+    }
+
+    /// Returns whether the name refers to a local that was defined in the current scope
+    /// or to a global.
+    pub(crate) fn is_in_current_scope(&self, name: &str) -> bool {
+        match self.data.get(name) {
+            Some(StoredValue::Locals(locals)) => locals
+                .last()
+                .is_some_and(|(scope, _)| *scope == self.current_scope),
+            Some(StoredValue::Global(_)) => true,
+            None => false,
+        }
+    }
+
     pub fn undefine(&mut self, name: impl AsRef<str>) -> Option<Atom> {
         match self.data.remove(name.as_ref())? {
             StoredValue::Global(global) => Some(global),
-            StoredValue::Locals(mut locals) => locals.pop().map(|(_, a)| a),
+            StoredValue::Locals(mut locals) => {
+                locals.pop().and_then(|(_, local)| local.into_atom())
+            }
         }
     }
 
     pub fn all_data(&self) -> impl Iterator<Item = (String, Atom)> {
         self.data
             .iter()
-            .filter_map(|(ident, value)| Some((ident.clone(), value.as_atom()?.clone())))
+            .filter_map(|(ident, value)| Some((ident.clone(), value.as_atom()?.into_owned())))
+    }
+
+    /// Returns the names of all values, without cloning the values like
+    /// [`all_data`](Self::all_data).
+    pub(crate) fn names(&self) -> impl Iterator<Item = &str> {
+        self.data
+            .iter()
+            .filter(|(_, value)| value.is_bound())
+            .map(|(ident, _)| ident.as_str())
     }
 
     pub fn all_globals(&self) -> impl Iterator<Item = (String, Atom)> {
@@ -0,0 +1,209 @@
+use crate::exception::NameError;
+use crate::interned_stdlib::INTERNED_STL;
+use crate::no_path;
+use crate::parsing::{build_program, tokenize};
+use crate::prelude::*;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+thread_local! {
+    /// The public names every STL module defines itself, by module name.
+    static STL_EXPORTS: Rc<BTreeMap<&'static str, Vec<String>>> = Rc::new(stl_exports());
+}
+
+/// Finds the names that the top-level `def` and `=` calls of every STL module define,
+/// without running any of them.
+fn stl_exports() -> BTreeMap<&'static str, Vec<String>> {
+    INTERNED_STL
+        .iter()
+        .filter(|(module, _)| **module != "prelude")
+        .filter_map(|(module, code)| {
+            let Ok(Argument::FunctionCall(program, _)) =
+                tokenize(code, no_path()).and_then(|tokens| build_program(tokens, no_path()))
+            else {
+                return None;
+            };
+            let names = program
+                .args
+                .iter()
+                .filter_map(|arg| match arg {
+                    Argument::FunctionCall(call, _)
+                        if &*call.name == "def" || &*call.name == "=" =>
+                    {
+                        match call.args.first() {
+                            Some(Argument::Variable(name, _)) if !name.starts_with('_') => {
+                                Some(name.clone())
+                            }
+                            _ => None,
+                        }
+                    }
+                    _ => None,
+                })
+                .collect();
+            Some((*module, names))
+        })
+        .collect()
+}
+
+/// The optimal string alignment distance between two strings, which is the number of
+/// insertions, deletions, substitutions and swaps of adjacent characters
+/// needed to turn one into the other.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+    // rows[i][j] is the distance between the first i chars of a and the first j chars of b
+    let mut rows = vec![(0..=b.len()).collect::<Vec<_>>()];
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let substitution = rows[i - 1][j - 1] + usize::from(a[i - 1] != b[j - 1]);
+            row[j] = substitution.min(rows[i - 1][j] + 1).min(row[j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(rows[i - 2][j - 2] + 1);
+            }
+        }
+        rows.push(row);
+    }
+    rows[a.len()][b.len()]
+}
+
+/// The largest edit distance a suggestion for the name may have.
+///
+/// Only a third of the name may be wrong and never all of it,
+/// so that short names do not get unrelated suggestions.
+fn max_distance(name: &str) -> usize {
+    let len = name.chars().count();
+    (len / 3).max(1).min(len.saturating_sub(1))
+}
+
+/// Returns whether the other name may be suggested for the name.
+///
+/// Names starting with `__` are internals of the STL,
+/// suggesting them is only helpful if the user already uses such a name.
+fn is_candidate(name: &str, candidate: &str) -> bool {
+    candidate != name && (name.starts_with("__") || !candidate.starts_with("__"))
+}
+
+/// A name that could not be found, together with the defined names that may be suggested
+/// instead.
+///
+/// Exceptions for unknown names are often caught without being shown,
+/// so the suggestion is only computed once their [message](Exception::message) is needed.
+/// Until then, only the defined names whose length is close enough to be suggested are kept,
+/// since the edit distance is at least the difference in length.
+#[derive(Debug, Clone)]
+pub(crate) struct UnknownName {
+    name: String,
+    defined: Vec<String>,
+}
+
+impl UnknownName {
+    fn new(name: &str, state: &State) -> Self {
+        let len = name.chars().count();
+        let max_distance = max_distance(name);
+        let defined = state
+            .storage
+            .names()
+            .filter(|candidate| {
+                candidate.chars().count().abs_diff(len) <= max_distance
+                    && is_candidate(name, candidate)
+            })
+            .map(str::to_string)
+            .collect();
+        Self {
+            name: name.to_string(),
+            defined,
+        }
+    }
+
+    /// Returns a hint for the name, such as "did you mean `print`?".
+    ///
+    /// The hint either names the closest defined values or the STL module which defines the
+    /// name if it was not imported yet.
+    pub(crate) fn suggestion(&self) -> Option<String> {
+        let name = self.name.as_str();
+        let stl_exports = STL_EXPORTS.with(Rc::clone);
+        if let Some((module, _)) = stl_exports
+            .iter()
+            .find(|(_, names)| names.iter().any(|n| n == name))
+        {
+            return Some(format!(
+                "`{name}` exists in the `{module}` module, add `import({module})`"
+            ));
+        }
+        let max_distance = max_distance(name);
+        let mut candidates = self
+            .defined
+            .iter()
+            .map(|candidate| (candidate.as_str(), None))
+            .chain(stl_exports.iter().flat_map(|(module, names)| {
+                names
+                    .iter()
+                    .filter(|n| !self.defined.contains(*n))
+                    .map(|n| (n.as_str(), Some(*module)))
+            }))
+            .filter(|(candidate, _)| is_candidate(name, candidate))
+            .map(|(candidate, module)| (edit_distance(name, candidate), candidate, module))
+            .filter(|(distance, ..)| *distance <= max_distance)
+            .collect::<Vec<_>>();
+        candidates.sort_unstable();
+        let closest = candidates.first()?.0;
+        let shown = candidates
+            .iter()
+            .take_while(|(distance, ..)| *distance == closest)
+            .take(3)
+            .map(|(_, candidate, module)| match module {
+                Some(module) => format!("`{candidate}` from the `{module}` module"),
+                None => format!("`{candidate}`"),
+            })
+            .collect::<Vec<_>>();
+        Some(format!("did you mean {}?", shown.join(" or ")))
+    }
+}
+
+impl State {
+    /// Constructs a `NameError` for a name that could not be found.
+    ///
+    /// Its [message](Exception::message) suggests similar names.
+    pub(crate) fn raise_unknown_name(&self, msg: String, name: &str) -> Exception {
+        let mut exception = self.raise(NameError, msg);
+        exception.unknown_name = Some(Box::new(UnknownName::new(name, self)));
+        exception
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distances() {
+        assert_eq!(edit_distance("print", "print"), 0);
+        assert_eq!(edit_distance("pritn", "print"), 1);
+        assert_eq!(edit_distance("prin", "print"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn suggestions() {
+        let mut state = State::new().with_cwd();
+        state.eval_more("=(counter, 1), =(countr, 2)").unwrap();
+        let suggestion = |name| UnknownName::new(name, &state).suggestion();
+        assert_eq!(suggestion("pritn").unwrap(), "did you mean `print`?");
+        assert_eq!(
+            suggestion("counte").unwrap(),
+            "did you mean `counter` or `countr`?"
+        );
+        assert_eq!(
+            suggestion("quicksort").unwrap(),
+            "`quicksort` exists in the `sorting` module, add `import(sorting)`"
+        );
+        assert_eq!(
+            suggestion("quicksrot").unwrap(),
+            "did you mean `quicksort` from the `sorting` module?"
+        );
+        assert_eq!(suggestion("xyz"), None);
+        assert_eq!(suggestion("y"), None);
+    }
+}